@@ -1,45 +1,248 @@
 use std::{
     ffi::OsStr,
+    io::{BufRead as _, BufReader},
     path::Path,
-    process::{Command, ExitStatus, Stdio},
+    process::{Child, Command, ExitStatus, Stdio},
+    time::{Duration, Instant},
 };
 
 use anyhow::{Context, anyhow};
 use log::{debug, info, warn};
 
+/// How often a running cargo is checked for having finished.
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Longest delay before re-running cargo, however many retries came before.
+const MAX_BACKOFF: Duration = Duration::from_secs(10 * 60);
+
+/// Cargo's messages about failures which may not happen again when cargo is re-run.
+const TRANSIENT_FAILURES: [&str; 13] = [
+    "spurious network error",
+    "timed out",
+    "timeout was reached",
+    "couldn't resolve host",
+    "couldn't connect to server",
+    "connection reset",
+    "failure when receiving data",
+    "ssl connect error",
+    "got 429",
+    "got 500",
+    "got 502",
+    "got 503",
+    "got 504",
+];
+
+/// How cargo invocations are retried and limited.
+#[derive(Debug, Clone)]
+pub struct Retries {
+    /// Number of times cargo is re-run after a transient failure.
+    pub retries: u32,
+    /// Delay before the first re-run, doubled before every next one, up to [`MAX_BACKOFF`].
+    pub backoff: Duration,
+    /// Time after which cargo is killed, and then re-run like after a transient failure.
+    pub timeout: Option<Duration>,
+}
+
+/// Outcome of running cargo.
+#[derive(Debug)]
+pub struct Run {
+    pub status: ExitStatus,
+    /// What cargo printed to stderr.
+    pub stderr: String,
+    /// Whether cargo was killed for running too long.
+    pub timed_out: bool,
+}
+
+impl Run {
+    /// Tell if cargo failed in a way that may not happen again, like a network failure.
+    pub fn failed_transiently(&self) -> bool {
+        !self.status.success() && (self.timed_out || is_transient(&self.stderr))
+    }
+}
+
+/// Run `cargo_cmd` in `cwd`, returning its exit status and what it printed to stderr.
+///
+/// Stderr is still forwarded line by line as cargo prints it, but, as it is not a terminal, cargo
+/// does not show progress bars. Cargo is re-run as `retries` says, and only the last run's status
+/// and stderr are returned.
 pub fn run<S>(
     cwd: impl AsRef<Path>,
     cargo_cmd: &str,
     args: impl IntoIterator<Item = S>,
     quiet: bool,
-) -> Result<(), anyhow::Error>
+    retries: &Retries,
+) -> Result<Run, anyhow::Error>
 where
     S: AsRef<OsStr>,
 {
-    let status = run_cargo_impl(cwd.as_ref(), cargo_cmd, args, false, quiet)?;
-    assert!(status.success());
+    let mut cmd = command(cwd.as_ref(), cargo_cmd, args, quiet)?;
+    cmd.stderr(Stdio::piped());
+    // In its own process group, cargo can be killed together with the git and rustc processes it
+    // spawned, which would otherwise outlive it, holding its stderr open. Out of the terminal's
+    // foreground group, it does not get the terminal's signals, which are forwarded to it then,
+    // and cannot read from the terminal, so git is told not to prompt for credentials.
+    #[cfg(unix)]
+    if retries.timeout.is_some() {
+        std::os::unix::process::CommandExt::process_group(&mut cmd, 0);
+        cmd.stdin(Stdio::null()).env("GIT_TERMINAL_PROMPT", "0");
+    }
+    let mut backoff = retries.backoff.min(MAX_BACKOFF);
+    for attempt in 1.. {
+        info!(cmd:?, attempt; "running cargo");
+        let run = run_once(&mut cmd, retries.timeout)
+            .with_context(|| format!("failed to invoke cargo {cargo_cmd}"))?;
+        if run.timed_out {
+            let timeout = retries.timeout.expect("only runs with a timeout time out");
+            warn!(attempt, timeout:?; "cargo timed out, killed it");
+            if !quiet {
+                eprintln!("cargo {cargo_cmd} timed out after {timeout:?}, killed it");
+            }
+        }
+        let transient = run.failed_transiently();
+        if !transient || attempt > retries.retries {
+            info!(attempt, status:% = run.status, transient; "cargo finished");
+            return Ok(run);
+        }
+        warn!(attempt, status:% = run.status, backoff:?; "cargo failed transiently, retrying");
+        std::thread::sleep(backoff);
+        backoff = backoff.saturating_mul(2).min(MAX_BACKOFF);
+    }
+    unreachable!("attempts are unlimited")
+}
+
+/// Run `cmd` once, killing it, and its process group on unix, after `timeout`.
+///
+/// On unix, `cmd` leads its own process group if there is a timeout.
+fn run_once(cmd: &mut Command, timeout: Option<Duration>) -> Result<Run, std::io::Error> {
+    let mut child = cmd.spawn()?;
+    #[cfg(unix)]
+    let forwarding = timeout.is_some().then(|| signals::forward_to(&child));
+    let stderr = BufReader::new(child.stderr.take().expect("stderr should be piped"));
+    // Read on another thread, so that a cargo which hangs without printing can be killed.
+    let reader = std::thread::spawn(move || {
+        stderr.lines().try_fold(String::new(), |mut stderr, line| {
+            let line = line?;
+            eprintln!("{line}");
+            stderr += &line;
+            stderr.push('\n');
+            Ok::<_, std::io::Error>(stderr)
+        })
+    });
+    // A timeout too long to represent never passes.
+    let deadline = timeout.and_then(|timeout| Instant::now().checked_add(timeout));
+    let mut timed_out = false;
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            kill(&mut child)?;
+            timed_out = true;
+            break child.wait()?;
+        }
+        std::thread::sleep(POLL_INTERVAL);
+    };
+    #[cfg(unix)]
+    drop(forwarding);
+    let stderr = reader.join().expect("stderr reader should not panic")?;
+    Ok(Run {
+        status,
+        stderr,
+        timed_out,
+    })
+}
+
+/// Kill `child` together with its process group, which it leads.
+#[cfg(unix)]
+fn kill(child: &mut Child) -> Result<(), std::io::Error> {
+    let group = libc::pid_t::try_from(child.id()).map_err(std::io::Error::other)?;
+    // SAFETY: kill only sends a signal, to the group of a child that was not waited for yet, so
+    // its id cannot have been reused.
+    if unsafe { libc::kill(-group, libc::SIGKILL) } == -1 {
+        return Err(std::io::Error::last_os_error());
+    }
     Ok(())
 }
 
-pub fn run_passthrough<S>(
-    cwd: impl AsRef<Path>,
-    cargo_cmd: &str,
-    args: impl IntoIterator<Item = S>,
-    quiet: bool,
-) -> Result<ExitStatus, anyhow::Error>
-where
-    S: AsRef<OsStr>,
-{
-    run_cargo_impl(cwd.as_ref(), cargo_cmd, args, true, quiet)
+#[cfg(not(unix))]
+fn kill(child: &mut Child) -> Result<(), std::io::Error> {
+    child.kill()
 }
 
-fn run_cargo_impl<S>(
+/// Forwarding of the signals that terminate cargo-lock-fetch to cargo's own process group, which
+/// the terminal does not send them to.
+#[cfg(unix)]
+mod signals {
+    use std::{
+        process::Child,
+        sync::{
+            Once,
+            atomic::{AtomicI32, Ordering},
+        },
+    };
+
+    /// Signals a terminal, or a process manager, stops cargo-lock-fetch with.
+    const FORWARDED: [libc::c_int; 3] = [libc::SIGINT, libc::SIGTERM, libc::SIGHUP];
+
+    /// Process group of the running cargo, 0 if there is none.
+    static GROUP: AtomicI32 = AtomicI32::new(0);
+
+    /// Forwards signals to the group of a cargo run, until dropped once the run is over.
+    pub struct Forwarding;
+
+    impl Drop for Forwarding {
+        fn drop(&mut self) {
+            GROUP.store(0, Ordering::SeqCst);
+        }
+    }
+
+    /// Forward the signals to the process group led by `child`.
+    pub fn forward_to(child: &Child) -> Forwarding {
+        static INSTALL: Once = Once::new();
+        INSTALL.call_once(|| {
+            for signal in FORWARDED {
+                // SAFETY: the handler only calls async-signal-safe functions. A signal ignored
+                // before, as by background jobs of shells, stays ignored.
+                unsafe {
+                    let handler = forward as extern "C" fn(libc::c_int) as libc::sighandler_t;
+                    if libc::signal(signal, handler) == libc::SIG_IGN {
+                        libc::signal(signal, libc::SIG_IGN);
+                    }
+                }
+            }
+        });
+        let group = libc::pid_t::try_from(child.id()).expect("process ids fit pid_t");
+        GROUP.store(group, Ordering::SeqCst);
+        Forwarding
+    }
+
+    extern "C" fn forward(signal: libc::c_int) {
+        let group = GROUP.load(Ordering::SeqCst);
+        // SAFETY: kill, signal and raise are async-signal-safe. The group is only set while its
+        // leader runs, up to right after it is waited for. Re-raised with the default
+        // action, the signal terminates cargo-lock-fetch as if it was not handled.
+        unsafe {
+            if group != 0 {
+                libc::kill(-group, signal);
+            }
+            libc::signal(signal, libc::SIG_DFL);
+            libc::raise(signal);
+        }
+    }
+}
+
+/// Tell if cargo's `stderr` reports a failure which may not happen again.
+fn is_transient(stderr: &str) -> bool {
+    let stderr = stderr.to_lowercase();
+    TRANSIENT_FAILURES.iter().any(|f| stderr.contains(f))
+}
+
+fn command<S>(
     cwd: &Path,
     cargo_cmd: &str,
     args: impl IntoIterator<Item = S>,
-    passthrough: bool,
     quiet: bool,
-) -> Result<ExitStatus, anyhow::Error>
+) -> Result<Command, anyhow::Error>
 where
     S: AsRef<OsStr>,
 {
@@ -62,31 +265,66 @@ where
             anyhow!("{:?} does not exist", dir)
         })?
     }
-    let (out_cfg, err_cfg) = if passthrough {
-        (Stdio::inherit(), Stdio::inherit())
-    } else {
-        (Stdio::null(), Stdio::piped())
-    };
     let mut cmd = Command::new(cargo_bin);
-    let cmd = cmd
-        .stdout(out_cfg)
-        .stderr(err_cfg)
+    cmd.stdout(Stdio::inherit())
+        .stderr(Stdio::inherit())
         .arg(cargo_cmd)
         .args(args)
         .current_dir(cwd);
     if quiet {
         cmd.arg("-q");
     }
-    info!(cmd:?; "running cargo");
-    let output = cmd
-        .output()
-        .with_context(|| format!("failed to invoke cargo {cargo_cmd}"))?;
-    if !passthrough && !output.status.success() {
-        let err =
-            String::from_utf8(output.stderr).context("cargo returned non-utf8 error output")?;
-        Err(std::io::Error::other(format!(
-            "\"cargo {cargo_cmd}\" returned error:\n{err}"
-        )))?
+    Ok(cmd)
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        process::{Command, Stdio},
+        time::{Duration, Instant},
+    };
+
+    use super::{is_transient, run_once};
+
+    #[test]
+    fn recognizes_transient_failures() {
+        assert!(is_transient(indoc::indoc! {"
+            error: failed to download from `https://static.crates.io/crates/itoa/1.0.18/download`
+
+            Caused by:
+              failed to get successful HTTP response from `https://static.crates.io/crates/itoa/1.0.18/download` (151.101.1.1), got 503
+        "}));
+        assert!(is_transient(
+            "error: failed to query replaced source registry `crates-io`\n\nCaused by:\n  [6] Couldn't resolve host name"
+        ));
+        assert!(!is_transient(
+            "error: failed to select a version for the requirement `itoa = \"=1.0.18\"`"
+        ));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn kills_whole_process_group_on_timeout() {
+        let mut cmd = Command::new("sh");
+        // The background sleep holds stderr open, as a git or rustc spawned by cargo could.
+        cmd.args(["-c", "sleep 30 & sleep 30"])
+            .stderr(Stdio::piped());
+        std::os::unix::process::CommandExt::process_group(&mut cmd, 0);
+        let start = Instant::now();
+
+        let run = run_once(&mut cmd, Some(Duration::from_millis(200))).expect("sh should run");
+
+        assert!(run.timed_out);
+        assert!(start.elapsed() < Duration::from_secs(10));
+    }
+
+    #[test]
+    fn never_times_out_with_unrepresentable_timeout() {
+        let mut cmd = Command::new("cargo");
+        cmd.arg("--version").stderr(Stdio::piped());
+
+        let run = run_once(&mut cmd, Some(Duration::MAX)).expect("cargo should run");
+
+        assert!(run.status.success() && !run.timed_out);
     }
-    Ok(output.status)
 }
@@ -2,20 +2,43 @@ use std::{fs::OpenOptions, io::Write as _, iter::once, path::Path};
 
 use anyhow::Context;
 
-use crate::registry_aliases::RegistryAliases;
+use crate::registry_aliases::{LocalSource, RegistryAliases};
 
-pub fn write_registries(
-    dir: impl AsRef<Path>,
-    registries: &RegistryAliases,
-) -> Result<(), anyhow::Error> {
-    use toml_edit::{DocumentMut, Table};
+pub fn registries_config(registries: &RegistryAliases) -> toml_edit::DocumentMut {
+    use toml_edit::Table;
 
+    // Registries standing in for local sources are replaced by them. Cargo never contacts a
+    // replaced registry, so it does not matter that its index URL is the local source's one.
+    let sources = Table::from_iter(registries.local_sources().flat_map(|(alias, uri, local)| {
+        let local_alias = format!("{alias}-local");
+        let (kind, path) = match local {
+            LocalSource::LocalRegistry(path) => ("local-registry", path),
+            LocalSource::Directory(path) => ("directory", path),
+        };
+        let local = Table::from_iter(once((kind, path.to_string_lossy().into_owned())));
+        let replaced = Table::from_iter([
+            ("registry", uri.to_string()),
+            ("replace-with", local_alias.clone()),
+        ]);
+        [(alias.to_string(), replaced), (local_alias, local)]
+    }));
     let registries = Table::from_iter(
         registries
             .iter()
             .map(|(alias, uri)| (alias, Table::from_iter(once(("index", uri))))),
     );
-    let config: DocumentMut = Table::from_iter(once(("registries", registries))).into();
+    let mut config = Table::from_iter(once(("registries", registries)));
+    if !sources.is_empty() {
+        config.insert("source", sources.into());
+    }
+    config.into()
+}
+
+pub fn write_registries(
+    dir: impl AsRef<Path>,
+    registries: &RegistryAliases,
+) -> Result<(), anyhow::Error> {
+    let config = registries_config(registries);
 
     let _ = std::fs::create_dir(dir.as_ref().join(".cargo"));
     let mut file = OpenOptions::new()
@@ -0,0 +1,264 @@
+//! Recognition of the packages that made cargo fail, and why, from what it printed to stderr.
+
+use cargo_lock::Package;
+
+/// Why cargo failed on a package, as far as its error message tells.
+#[derive(thiserror::Error, Debug, Clone, PartialEq, Eq)]
+pub enum Problem {
+    #[error("checksum does not match the lockfile")]
+    ChecksumMismatch,
+    #[error("version is yanked")]
+    Yanked,
+    #[error("{0}")]
+    AuthRequired(String),
+    #[error("git revision not found")]
+    GitRevisionMissing,
+    #[error("version not found in the index")]
+    NotInIndex,
+    #[error("HTTP status {0}")]
+    Http(u16),
+    #[error("cargo failed on it")]
+    Other,
+}
+
+/// A package cargo failed on.
+#[derive(Debug)]
+pub struct Diagnostic<'a> {
+    pub package: &'a Package,
+    /// Alias of the package's registry in the generated `.cargo/config.toml`, if it has one.
+    pub registry: Option<&'a str>,
+    pub problem: Problem,
+}
+
+impl std::fmt::Display for Diagnostic<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let Package { name, version, .. } = self.package;
+        write!(f, "`{name} {version}` from ")?;
+        match (&self.package.source, self.registry) {
+            (Some(source), Some(registry)) => write!(f, "`{registry}` ({})", source.url())?,
+            (Some(source), None) if source.is_default_registry() => {
+                write!(f, "`crates-io` ({})", source.url())?
+            }
+            (Some(source), None) => write!(f, "{source}")?,
+            (None, _) => write!(f, "no source")?,
+        }
+        write!(f, ": {}", self.problem)
+    }
+}
+
+/// Find the packages among `packages`, given with their `[dependencies]` keys and registry
+/// aliases, that cargo's `stderr` blames, and why.
+///
+/// Cargo quotes what it talks about in backticks: packages as `name vversion`, requirements as
+/// `name = "=version"` or `key = "=version"`, dependencies as just `key`, and downloads as URLs,
+/// which name the crate and version in their path. A dependency key shared by packages of
+/// different sub-crates blames all of them. Each error, with its causes, is classified on its own.
+pub fn diagnose<'a>(
+    stderr: &str,
+    packages: impl IntoIterator<Item = (&'a str, &'a Package, Option<&'a str>)>,
+) -> Vec<Diagnostic<'a>> {
+    let errors = errors(stderr)
+        .into_iter()
+        .map(|error| {
+            let quoted = error
+                .split('`')
+                .skip(1)
+                .step_by(2)
+                .map(str::to_owned)
+                .collect::<Vec<_>>();
+            (quoted, classify(&error))
+        })
+        .collect::<Vec<_>>();
+    packages
+        .into_iter()
+        .filter_map(|(key, package, registry)| {
+            let (_, problem) = errors
+                .iter()
+                .find(|(quoted, _)| quoted.iter().any(|q| mentions(q, key, package)))?;
+            Some(Diagnostic {
+                package,
+                registry,
+                problem: problem.clone(),
+            })
+        })
+        .collect()
+}
+
+/// Split `stderr` into cargo's error messages, each with its causes and notes, leaving out
+/// warnings and everything before the first error.
+fn errors(stderr: &str) -> Vec<String> {
+    let mut errors = vec![];
+    let mut current: Option<String> = None;
+    for line in stderr.lines() {
+        if line.starts_with("error") || line.starts_with("warning:") {
+            errors.extend(current.take());
+            if line.starts_with("error") {
+                current = Some(String::new());
+            }
+        }
+        if let Some(ref mut error) = current {
+            error.push_str(line);
+            error.push('\n');
+        }
+    }
+    errors.extend(current);
+    errors
+}
+
+fn classify(error: &str) -> Problem {
+    let error = error.to_lowercase();
+    let status = error.split("got ").skip(1).find_map(|rest| {
+        rest.get(..3)
+            .filter(|s| s.chars().all(|c| c.is_ascii_digit()))
+            .and_then(|s| s.parse::<u16>().ok())
+    });
+    let has = |phrases: &[&str]| phrases.iter().any(|p| error.contains(p));
+    if has(&[
+        "failed to verify the checksum",
+        "changed between lock files",
+    ]) {
+        Problem::ChecksumMismatch
+    } else if has(&["is yanked"]) {
+        Problem::Yanked
+    } else if status == Some(401) {
+        Problem::AuthRequired("401 Unauthorized".to_string())
+    } else if status == Some(403) {
+        Problem::AuthRequired("403 Forbidden".to_string())
+    } else if has(&[
+        "authentication required",
+        "token rejected",
+        "no token found",
+    ]) {
+        Problem::AuthRequired("authentication required".to_string())
+    } else if has(&[
+        "revspec",
+        "object not found",
+        "no match for id",
+        "failed to find branch",
+        "failed to find tag",
+    ]) {
+        Problem::GitRevisionMissing
+    } else if has(&["failed to select a version", "no matching package"]) {
+        Problem::NotInIndex
+    } else if let Some(status) = status {
+        Problem::Http(status)
+    } else {
+        Problem::Other
+    }
+}
+
+fn mentions(quoted: &str, key: &str, package: &Package) -> bool {
+    let name = package.name.as_str();
+    let version = package.version.to_string();
+    if quoted == key
+        || quoted == format!("{name} v{version}")
+        || quoted == format!("{key} = \"={version}\"")
+        || quoted == format!("{name} = \"={version}\"")
+    {
+        return true;
+    }
+    let crate_file = format!("{name}-{version}.crate");
+    let segments = quoted.split('/').collect::<Vec<_>>();
+    quoted.contains("://")
+        && (segments.contains(&crate_file.as_str())
+            || segments.windows(2).any(|w| w == [name, version.as_str()]))
+}
+
+#[cfg(test)]
+mod test {
+    use std::str::FromStr as _;
+
+    use cargo_lock::Lockfile;
+    use itertools::Itertools as _;
+
+    use super::diagnose;
+
+    const LOCKFILE: &str = r#"
+[[package]]
+name = "gone"
+version = "1.0.0"
+source = "registry+https://github.com/rust-lang/crates.io-index"
+
+[[package]]
+name = "syn"
+version = "1.0.109"
+source = "registry+https://github.com/rust-lang/crates.io-index"
+
+[[package]]
+name = "syn"
+version = "2.0.118"
+source = "registry+https://github.com/rust-lang/crates.io-index"
+
+[[package]]
+name = "private"
+version = "0.3.0"
+source = "sparse+https://my.registry/index/"
+
+[[package]]
+name = "tool"
+version = "0.1.0"
+source = "git+https://git.example.com/tool#0123456789abcdef0123456789abcdef01234567"
+"#;
+
+    fn diagnostics(stderr: &str) -> Vec<String> {
+        let lockfile = Lockfile::from_str(LOCKFILE).expect("fixture should parse");
+        let keys = ["gone", "syn-1_0_109", "syn-2_0_118", "private", "tool"];
+        let registries = [None, None, None, Some("reg2"), None];
+        let packages = keys
+            .into_iter()
+            .zip(&lockfile.packages)
+            .zip(registries)
+            .map(|((k, p), r)| (k, p, r));
+        diagnose(stderr, packages)
+            .iter()
+            .map(ToString::to_string)
+            .collect_vec()
+    }
+
+    #[test]
+    fn describes_failures_of_packages() {
+        let stderr = indoc::indoc! {r#"
+                Updating `reg2` index
+            error: failed to download from `https://my.registry/dl/private/0.3.0/download`
+
+            Caused by:
+              failed to get successful HTTP response from `https://my.registry/dl/private/0.3.0/download` (my.registry), got 401
+              body:
+              Unauthorized
+        "#};
+
+        assert_eq!(
+            diagnostics(stderr),
+            ["`private 0.3.0` from `reg2` (https://my.registry/index/): 401 Unauthorized"]
+        );
+    }
+
+    #[test]
+    fn classifies_common_failures() {
+        let stderr = indoc::indoc! {r#"
+            warning: spurious network error (3 tries remaining): `gone v1.0.0` timed out
+            error: failed to select a version for the requirement `syn = "=1.0.109"` (locked to 1.0.109)
+            candidate versions found which didn't match: 2.0.118
+            location searched: crates.io index
+            required by package `fake v0.1.0`
+            version 1.0.109 is yanked
+            error: failed to get `tool` as a dependency of package `fake v0.1.0`
+
+            Caused by:
+              failed to load source for dependency `tool`
+
+            Caused by:
+              revspec '0123456789abcdef0123456789abcdef01234567' not found; class=Reference (4)
+            error: failed to verify the checksum of `syn v2.0.118`
+        "#};
+
+        assert_eq!(
+            diagnostics(stderr),
+            [
+                "`syn 1.0.109` from `crates-io` (https://github.com/rust-lang/crates.io-index): version is yanked",
+                "`syn 2.0.118` from `crates-io` (https://github.com/rust-lang/crates.io-index): checksum does not match the lockfile",
+                "`tool 0.1.0` from git+https://git.example.com/tool#0123456789abcdef0123456789abcdef01234567: git revision not found",
+            ]
+        );
+    }
+}
@@ -0,0 +1,337 @@
+//! Locations of downloaded crates in cargo's home directory.
+
+use std::{
+    hash::{Hash, Hasher},
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context as _;
+use cargo_lock::{Package, SourceId, package::SourceKind};
+
+/// Cargo's default registry, which it reaches through the sparse protocol unless configured
+/// otherwise.
+const CRATES_IO_SPARSE_INDEX: &str = "sparse+https://index.crates.io/";
+
+pub fn cargo_home() -> Result<PathBuf, anyhow::Error> {
+    home::cargo_home().context("could not determine cargo home directory")
+}
+
+/// Name of the directory of registry `source` in `registry/cache` and `registry/src`.
+///
+/// The name is made of the index host and a hash of the source id, computed the same way as cargo
+/// does. Returns `None` for sources which are not remote registries.
+pub fn registry_dir_name(source: &SourceId) -> Option<String> {
+    // Discriminants of cargo's own `SourceKind`, which are part of the hash.
+    let (kind, url): (isize, _) = match source.kind() {
+        SourceKind::Registry | SourceKind::SparseRegistry if source.is_default_registry() => {
+            (3, CRATES_IO_SPARSE_INDEX.to_string())
+        }
+        SourceKind::Registry => (2, source.url().to_string()),
+        SourceKind::SparseRegistry => (3, format!("sparse+{}", source.url())),
+        _ => return None,
+    };
+    // Cargo hashes the canonical url, which has no trailing slash, unless the path is just "/".
+    let host_start = url.find("://").map_or(0, |i| i + 3);
+    let url = match url[host_start..].find('/') {
+        Some(path_start) if host_start + path_start + 1 < url.len() && url.ends_with('/') => {
+            url[..url.len() - 1].to_string()
+        }
+        _ => url,
+    };
+    let host = url
+        .trim_start_matches("sparse+")
+        .split_once("://")
+        .and_then(|(_, rest)| rest.split(['/', ':']).next())
+        .unwrap_or_default();
+    Some(format!("{host}-{}", short_hash((kind, url.as_str()))))
+}
+
+/// Name of the directory of git `source` in `git/db` and `git/checkouts`.
+///
+/// The name is made of the last path segment of the canonical repository url and a hash of that
+/// url, computed the same way as cargo does. Returns `None` for sources which are not git.
+pub fn git_dir_name(source: &SourceId) -> Option<String> {
+    if !source.is_git() {
+        return None;
+    }
+    let mut url = source.url().clone();
+    url.set_query(None);
+    url.set_fragment(None);
+    // Cargo canonicalizes the url: without a trailing slash, with lowercase paths on github, and
+    // without a `.git` suffix.
+    if url.path().len() > 1 && url.path().ends_with('/') {
+        let path = url.path().trim_end_matches('/').to_string();
+        url.set_path(&path);
+    }
+    if url.host_str() == Some("github.com") {
+        let path = url.path().to_lowercase();
+        url.set_path(&path);
+    }
+    if let Some(path) = url.path().strip_suffix(".git") {
+        let path = path.to_string();
+        url.set_path(&path);
+    }
+    let ident = url
+        .path_segments()
+        .and_then(|mut s| s.next_back())
+        .filter(|s| !s.is_empty())
+        .unwrap_or("_empty");
+    Some(format!("{ident}-{}", short_hash(url.as_str())))
+}
+
+/// Hash of `value` as cargo uses it in directory names: the 64 bit stable hash, not the full 128
+/// bit one, in hex.
+fn short_hash(value: impl Hash) -> String {
+    let mut hasher = rustc_stable_hash::StableSipHasher128::new();
+    value.hash(&mut hasher);
+    Hasher::finish(&hasher)
+        .to_le_bytes()
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect()
+}
+
+/// Path of the `.crate` file of registry `package` in the registry cache of `cargo_home`.
+pub fn cached_crate_path(cargo_home: &Path, package: &Package) -> Option<PathBuf> {
+    let dir_name = registry_dir_name(package.source.as_ref()?)?;
+    let file_name = format!("{}-{}.crate", package.name, package.version);
+    Some(
+        cargo_home
+            .join("registry/cache")
+            .join(dir_name)
+            .join(file_name),
+    )
+}
+
+/// Directory of crate `name` in an index, which is also used by `{prefix}` in `dl` templates.
+pub fn index_prefix(name: &str) -> String {
+    match name.len() {
+        1 => "1".to_string(),
+        2 => "2".to_string(),
+        3 => format!("3/{}", &name[..1]),
+        _ => format!("{}/{}", &name[..2], &name[2..4]),
+    }
+}
+
+/// Path of the index cache file of crate `name` in `index_dir`, a registry's directory in
+/// `registry/index`.
+pub fn index_cache_path(index_dir: &Path, name: &str) -> PathBuf {
+    let name = name.to_lowercase();
+    index_dir
+        .join(".cache")
+        .join(index_prefix(&name))
+        .join(name)
+}
+
+/// Check if `package` is already downloaded to `cargo_home`.
+///
+/// Registry packages are looked up in the registry cache, git packages in the repository database
+/// cargo checks out from, which must contain their precise revision. Packages from other sources
+/// are never cached.
+pub fn is_cached(cargo_home: &Path, package: &Package) -> bool {
+    let Some(source) = &package.source else {
+        return false;
+    };
+    if let Some(path) = cached_crate_path(cargo_home, package) {
+        return path.is_file();
+    }
+    match (git_dir_name(source), source.precise()) {
+        (Some(dir_name), Some(precise)) => {
+            git_db_contains(&cargo_home.join("git/db").join(dir_name), precise)
+        }
+        _ => false,
+    }
+}
+
+/// Check if the bare repository `db` contains object `id`, loose or in a pack.
+fn git_db_contains(db: &Path, id: &str) -> bool {
+    let Some(raw) = hex_id(id) else {
+        return false;
+    };
+    let objects = db.join("objects");
+    if objects.join(&id[..2]).join(&id[2..]).is_file() {
+        return true;
+    }
+    let Ok(packs) = std::fs::read_dir(objects.join("pack")) else {
+        return false;
+    };
+    packs
+        .filter_map(Result::ok)
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().is_some_and(|e| e == "idx"))
+        .filter_map(|path| std::fs::read(path).ok())
+        .any(|index| pack_index_contains(&index, &raw))
+}
+
+/// Parse a 40 digit hex object id.
+fn hex_id(id: &str) -> Option<[u8; 20]> {
+    if id.len() != 40 || !id.is_ascii() {
+        return None;
+    }
+    let mut raw = [0; 20];
+    for (i, byte) in raw.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&id[2 * i..2 * i + 2], 16).ok()?;
+    }
+    Some(raw)
+}
+
+/// Check if a version 2 pack index lists object `id`.
+///
+/// The index starts with a magic number and version, followed by a fan-out table of the number of
+/// objects with a first byte up to each value, and the sorted object ids.
+fn pack_index_contains(index: &[u8], id: &[u8; 20]) -> bool {
+    const HEADER: &[u8] = b"\xfftOc\0\0\0\x02";
+    const FANOUT_LEN: usize = 256 * 4;
+    if !index.starts_with(HEADER) || index.len() < HEADER.len() + FANOUT_LEN {
+        return false;
+    }
+    let fanout = |i: usize| {
+        let start = HEADER.len() + 4 * i;
+        u32::from_be_bytes(index[start..start + 4].try_into().expect("4 bytes")) as usize
+    };
+    let first = usize::from(id[0]);
+    let start = if first == 0 { 0 } else { fanout(first - 1) };
+    let end = fanout(first);
+    let ids = &index[HEADER.len() + FANOUT_LEN..];
+    if ids.len() < end * 20 || start > end {
+        return false;
+    }
+    ids[start * 20..end * 20]
+        .chunks_exact(20)
+        .collect::<Vec<_>>()
+        .binary_search(&&id[..])
+        .is_ok()
+}
+
+#[cfg(test)]
+mod test {
+    use std::str::FromStr as _;
+
+    use cargo_lock::{Lockfile, SourceId};
+
+    use super::{git_dir_name, is_cached, pack_index_contains, registry_dir_name};
+
+    fn dir_name(source_url: &str) -> Option<String> {
+        registry_dir_name(&SourceId::from_url(source_url).expect("source url should parse"))
+    }
+
+    #[test]
+    fn crates_io_uses_sparse_index_directory() {
+        for url in [
+            "registry+https://github.com/rust-lang/crates.io-index",
+            "sparse+https://index.crates.io/",
+        ] {
+            assert_eq!(
+                dir_name(url).as_deref(),
+                Some("index.crates.io-1949cf8c6b5b557f")
+            );
+        }
+    }
+
+    #[test]
+    fn other_registries_are_named_after_their_host() {
+        let git = dir_name("registry+https://my.registry/index").expect("registry has a dir");
+        let sparse = dir_name("sparse+https://my.registry/index/").expect("registry has a dir");
+
+        assert!(git.starts_with("my.registry-"));
+        assert!(sparse.starts_with("my.registry-"));
+        assert_ne!(git, sparse);
+        // As cargo names it.
+        assert_eq!(
+            dir_name("sparse+http://127.0.0.1:42359/index/").as_deref(),
+            Some("127.0.0.1-d94777e5b36e6551")
+        );
+        assert_eq!(
+            dir_name("git+https://example.com/itoa?rev=1234567#1234567"),
+            None
+        );
+    }
+
+    #[test]
+    fn git_repositories_are_named_as_cargo_names_them() {
+        let name = |url| git_dir_name(&SourceId::from_url(url).expect("source url should parse"));
+
+        assert_eq!(
+            name("git+file:///tmp/gitdb/Repo.Git/?rev=83bd54c#83bd54c12d9d342d8d3b1689b58abe6dd927c3b2")
+                .as_deref(),
+            Some("Repo.Git-5f7038bbddd03473")
+        );
+        assert_eq!(
+            name("git+https://github.com/Org/Tool.git#0123456789abcdef0123456789abcdef01234567"),
+            name("git+https://github.com/org/tool#0123456789abcdef0123456789abcdef01234567")
+        );
+        assert_eq!(name("registry+https://my.registry/index"), None);
+    }
+
+    #[test]
+    fn finds_objects_in_pack_indexes() {
+        let ids = [[0x01; 20], [0x83; 20], [0x84; 20]];
+        let mut index = b"\xfftOc\0\0\0\x02".to_vec();
+        for byte in 0..=255u8 {
+            let count = ids.iter().filter(|id| id[0] <= byte).count() as u32;
+            index.extend(count.to_be_bytes());
+        }
+        index.extend(ids.iter().flatten());
+
+        assert!(pack_index_contains(&index, &[0x83; 20]));
+        assert!(!pack_index_contains(&index, &[0x82; 20]));
+        assert!(!pack_index_contains(&index[..100], &[0x83; 20]));
+    }
+
+    #[test]
+    fn finds_cached_registry_crates_and_git_revisions() {
+        let lockfile = Lockfile::from_str(indoc::indoc! {r#"
+            [[package]]
+            name = "itoa"
+            version = "1.0.11"
+            source = "git+https://example.com/itoa.git?rev=0123456#0123456789abcdef0123456789abcdef01234567"
+
+            [[package]]
+            name = "memchr"
+            version = "2.8.0"
+            source = "registry+https://github.com/rust-lang/crates.io-index"
+            checksum = "f8ca58f447f06ed17d5fc4043ce1b10dd205e060fb3ce5b979b8ed8e59ff3f79"
+
+            [[package]]
+            name = "syn"
+            version = "2.0.118"
+            source = "registry+https://github.com/rust-lang/crates.io-index"
+            checksum = "cafebabecafebabecafebabecafebabecafebabecafebabecafebabecafebabe"
+        "#})
+        .expect("fixture should parse");
+        let home = temp_dir::TempDir::new().expect("temp dir should be created");
+        let cache = home
+            .path()
+            .join("registry/cache/index.crates.io-1949cf8c6b5b557f");
+        std::fs::create_dir_all(&cache).expect("cache dir should be created");
+        std::fs::write(cache.join("memchr-2.8.0.crate"), "").expect("crate should be written");
+        let git = lockfile.packages[0]
+            .source
+            .as_ref()
+            .expect("fixture has source");
+        let db = home
+            .path()
+            .join("git/db")
+            .join(git_dir_name(git).expect("source is git"));
+        let object = db.join("objects/01/23456789abcdef0123456789abcdef01234567");
+        std::fs::create_dir_all(object.parent().expect("object is in a dir"))
+            .expect("objects dir should be created");
+        std::fs::write(&object, "").expect("object should be written");
+        // A checkout alone cannot be built from offline, without its revision in the database.
+        let checkout = home
+            .path()
+            .join("git/checkouts/itoa-0011223344556677/0123456");
+        std::fs::create_dir_all(&checkout).expect("checkout dir should be created");
+        std::fs::write(checkout.join(".cargo-ok"), "").expect("marker should be written");
+
+        let cached = lockfile
+            .packages
+            .iter()
+            .map(|p| is_cached(home.path(), p))
+            .collect::<Vec<_>>();
+
+        assert_eq!(cached, vec![true, true, false]);
+        std::fs::remove_file(object).expect("object should be removed");
+        assert!(!is_cached(home.path(), &lockfile.packages[0]));
+    }
+}
@@ -1,33 +1,247 @@
 use std::{
+    collections::{HashMap, HashSet},
+    ffi::OsStr,
     iter::once,
     path::{Path, PathBuf},
     process::ExitCode,
     str::FromStr as _,
+    time::Duration,
 };
 
 use anyhow::{Context, anyhow};
 use cargo_lock::{
-    Lockfile, Name, Package, SourceId, Version,
+    Lockfile, Package, Patch, SourceId,
     package::{GitReference, SourceKind},
 };
-use itertools::{Either, Itertools as _};
+use itertools::Itertools as _;
 use log::{error, warn};
 use unwrap_infallible::UnwrapInfallible as _;
 
 use crate::batches;
 use crate::cargo;
 use crate::cargo_config_toml;
+use crate::cargo_failures;
+use crate::cargo_home;
 use crate::cargo_toml;
+use crate::check;
 use crate::cli::CargoLockFetchCli;
+use crate::dependency_graph;
+use crate::download;
+use crate::local_packages::{self, LocalPackage};
 use crate::lockfile_synth;
-use crate::registry_aliases::RegistryAliases;
+use crate::lockfiles::{self, PackageKey};
+use crate::package_filter;
+use crate::registry_aliases::{LocalSource, RegistryAliases};
+use crate::report;
+use crate::vendor;
+use crate::verify;
+
+/// Name of the root crate of every generated project.
+const ROOT_CRATE: &str = "fake";
 
 pub fn main(cli: &CargoLockFetchCli) -> Result<ExitCode, anyhow::Error> {
     env_logger::init();
 
-    let lockfile = Lockfile::load(&cli.lockfile_path)
-        .with_context(|| format!("could not load lock file {}", cli.lockfile_path))?;
-    let resolve_version = lockfile.version;
+    let paths = if let Some(ref dir) = cli.discover {
+        let paths = lockfiles::discover(dir)?;
+        if paths.is_empty() {
+            Err(Failure::InvalidLockfile.of(anyhow!("no Cargo.lock found in {dir}")))?;
+        }
+        if !cli.quiet {
+            for path in &paths {
+                eprintln!("found lockfile: {}", path.display());
+            }
+        }
+        paths
+    } else {
+        cli.lockfile_path.iter().map(PathBuf::from).collect()
+    };
+    let (lockfiles, master_branches) =
+        lockfiles::load(&paths).map_err(|e| Failure::InvalidLockfile.of(e))?;
+    // Taken before fetching, so that a lockfile changed meanwhile is not stamped as processed.
+    let digest = if cli.stamp.is_some() {
+        Some(lockfiles::digest(&paths).map_err(|e| Failure::InvalidLockfile.of(e))?)
+    } else {
+        None
+    };
+    if let Some(missing) = cli.package.iter().find(|member| {
+        !lockfiles
+            .iter()
+            .any(|l| dependency_graph::members(&l.packages).any(|m| m == member.as_str()))
+    }) {
+        let known = lockfiles
+            .iter()
+            .flat_map(|l| dependency_graph::members(&l.packages))
+            .unique()
+            .join(", ");
+        Err(Failure::InvalidLockfile.of(anyhow!(
+            "package {missing} is not a workspace member, known members: {known}"
+        )))?;
+    }
+    // A lone local crate of a lockfile is its root package, which is not worth mentioning.
+    let lone_roots: HashSet<_> = lockfiles
+        .iter()
+        .filter_map(|l| {
+            l.packages
+                .iter()
+                .filter(|p| local_packages::is_local(p))
+                .exactly_one()
+                .ok()
+        })
+        .map(|p| (p.name.clone(), p.version.clone()))
+        .collect();
+    // Before merging, which keeps just one of the same-named members of different workspaces.
+    let lockfiles = lockfiles::merge(lockfiles.into_iter().map(|mut lockfile| {
+        if !cli.package.is_empty() {
+            lockfile.packages = dependency_graph::members_closure(lockfile.packages, &cli.package);
+        }
+        lockfile
+    }));
+    let previous: HashSet<_> = if let Some(ref since) = cli.since {
+        let previous = std::fs::read_to_string(since)
+            .map_err(anyhow::Error::from)
+            .and_then(|previous| lockfiles::parse(&previous))
+            .with_context(|| format!("could not load previous lock file {since}"))
+            .map_err(|e| Failure::InvalidLockfile.of(e))?;
+        previous
+            .packages
+            .iter()
+            .map(lockfiles::package_key)
+            .collect()
+    } else {
+        HashSet::new()
+    };
+
+    let plan = |registries: &mut _, dropped: &_| {
+        plan_projects(
+            &lockfiles,
+            &master_branches,
+            registries,
+            &previous,
+            dropped,
+            cli,
+        )
+    };
+    let mut registries = RegistryAliases::new();
+    let mut projects = plan(&mut registries, &HashSet::new())?;
+    if cli.strict {
+        let unused_patches: HashSet<_> = projects
+            .iter()
+            .flat_map(|p| p.unused_patches.iter().cloned())
+            .collect();
+        let issues = projects
+            .iter()
+            .flat_map(|p| p.packages().chain(&p.local))
+            .unique_by(|p| lockfiles::package_key(p))
+            .filter_map(|p| {
+                let issue = non_reproducible(p, &unused_patches)?;
+                let source = p
+                    .source
+                    .as_ref()
+                    .map_or("no source".to_string(), |s| s.to_string());
+                Some(format!("{} {} ({source}): {issue}", p.name, p.version))
+            })
+            .collect_vec();
+        if !issues.is_empty() {
+            Err(Failure::UnsupportedSource.of(anyhow!(
+                "non-reproducible crates found with --strict:\n  {}",
+                issues.join("\n  ")
+            )))?;
+        }
+    }
+    let local = local_packages::locate(
+        projects
+            .iter()
+            .flat_map(|p| &p.local)
+            .unique_by(|p| lockfiles::package_key(p)),
+        cli.workspace_root.as_deref().map(Path::new),
+    )
+    .context("failed to locate local crates")?;
+    if !cli.quiet {
+        let mentioned = |p: &&LocalPackage| {
+            cli.workspace_root.is_some()
+                || !lone_roots.contains(&(p.name.clone(), p.version.clone()))
+        };
+        for p in local.iter().filter(mentioned) {
+            let path = p
+                .path
+                .as_ref()
+                .map_or("location unknown".to_string(), |p| p.display().to_string());
+            eprintln!(
+                "not fetching local crate: {} {} ({path})",
+                p.name, p.version
+            );
+        }
+    }
+
+    // Gathered before cargo runs, to tell which packages were cached already.
+    let packages_report = if cli.report.is_some() {
+        Some(packages_report(&projects, &cargo_home::cargo_home()?))
+    } else {
+        None
+    };
+    let write_report = |cargo_status, failed_packages| {
+        if let (Some(path), Some(packages)) = (&cli.report, packages_report) {
+            let report = report::Report {
+                cargo_status,
+                packages,
+                failed_packages,
+                local_packages: local,
+            };
+            report::write(path, &report).context("failed to write report")?;
+        }
+        Ok::<_, anyhow::Error>(())
+    };
+
+    if cli.dry_run {
+        print_plan(&projects, &registries);
+        write_report(None, vec![])?;
+        return Ok(ExitCode::SUCCESS);
+    }
+    if cli.check {
+        let cargo_home = cargo_home::cargo_home()?;
+        let vendor_dir = cli.vendor_dir.as_deref().map(Path::new);
+        let missing = projects
+            .iter()
+            .flat_map(Project::packages)
+            .unique_by(|p| lockfiles::package_key(p))
+            .filter(|p| !check::is_available(&cargo_home, p, vendor_dir))
+            .collect_vec();
+        for p in &missing {
+            let source = p.source.as_ref().expect("fetched packages have a source");
+            println!("{}@{} ({source})", p.name, p.version);
+        }
+        if !missing.is_empty() {
+            Err(Failure::Crates.of(anyhow!("{} crates are missing", missing.len())))?;
+        }
+        return Ok(ExitCode::SUCCESS);
+    }
+    let write_stamp = || {
+        if let (Some(path), Some(digest)) = (&cli.stamp, &digest) {
+            std::fs::write(path, format!("{digest}\n"))
+                .with_context(|| format!("failed to write stamp file {path}"))?;
+        }
+        Ok::<_, anyhow::Error>(())
+    };
+
+    // Re-running with an unchanged lockfile is common, and then there is nothing to fetch. The
+    // project is still generated when asked to be kept.
+    let keeps_project = cli.keep_tmp || cli.tmp_dir.is_some();
+    if cli.vendor_dir.is_none() && !cli.verify && !cli.repair && !keeps_project {
+        let cargo_home = cargo_home::cargo_home()?;
+        if projects
+            .iter()
+            .flat_map(Project::packages)
+            .all(|p| check::is_available(&cargo_home, p, None))
+        {
+            if !cli.quiet {
+                eprintln!("nothing to do: all crates are in cargo's cache already");
+            }
+            write_report(None, vec![])?;
+            write_stamp()?;
+            return Ok(ExitCode::SUCCESS);
+        }
+    }
 
     let dir: Box<dyn AsRef<Path>> = if let Some(ref dir) = cli.tmp_dir {
         Box::new(PathBuf::from_str(dir).unwrap_infallible()) as _
@@ -44,145 +258,761 @@ pub fn main(cli: &CargoLockFetchCli) -> Result<ExitCode, anyhow::Error> {
         }
         Box::new(dir) as _
     };
+    let dir = (*dir).as_ref();
 
-    cargo::run(
-        dir.as_ref(),
-        "init",
-        [".", "--name", "fake", "--vcs", "none"],
-        cli.quiet,
-    )
-    .context("failed to create main project")?;
-
-    let (packages, local): (Vec<_>, Vec<_>) = lockfile.packages.into_iter().partition_map(|p| {
-        if p.source.is_some() {
-            Either::Left((p.name.clone(), p))
-        } else {
-            Either::Right(p)
+    write_projects(dir, &projects, &registries)?;
+    let mut fetched = fetch(dir, &projects, &registries, cli)?;
+    let mut failed = vec![];
+    if cli.keep_going {
+        (fetched, failed) = keep_going(dir, fetched, &mut projects, &mut registries, plan, cli)?;
+    }
+    // Cargo is trusted to have downloaded what the lockfile references only without --verify.
+    let mut bad_crates = vec![];
+    if fetched.success() && (cli.verify || cli.repair) {
+        let cargo_home = cargo_home::cargo_home()?;
+        let packages = || projects.iter().flat_map(Project::packages);
+        bad_crates = verify::verify(&cargo_home, packages());
+        if !bad_crates.is_empty() && cli.repair {
+            if !cli.quiet {
+                for b in &bad_crates {
+                    eprintln!("repairing crate: {} {}: {}", b.name, b.version, b.problem);
+                }
+            }
+            verify::remove(&bad_crates).context("failed to remove corrupted crates")?;
+            fetched = fetch(dir, &projects, &registries, cli)?;
+            bad_crates = verify::verify(&cargo_home, packages());
         }
-    });
-    if local.len() > 1 {
-        warn!(crates:? = local; "a crate other than root crate has no source");
     }
+    write_report(
+        fetched.cargo.as_ref().and_then(|r| r.status.code()),
+        failed.clone(),
+    )?;
+    if !fetched.failed_downloads.is_empty() {
+        Err(Failure::Network.of(anyhow!(
+            "failed to download crates:\n  {}",
+            fetched
+                .failed_downloads
+                .iter()
+                .map(|(p, reason)| format!("{} {}: {reason}", p.name, p.version))
+                .join("\n  ")
+        )))?;
+    }
+    if let Some(run) = fetched.cargo.filter(|r| !r.status.success()) {
+        let diagnostics = projects
+            .iter()
+            .flat_map(|project| project.diagnose(&run.stderr))
+            .map(|(_, _, description)| description)
+            .unique()
+            .collect_vec();
+        let error = if diagnostics.is_empty() {
+            anyhow!("cargo failed: {}", run.status)
+        } else {
+            anyhow!("cargo failed on crates:\n  {}", diagnostics.join("\n  "))
+        };
+        let failure = if run.failed_transiently() {
+            Failure::Network
+        } else {
+            Failure::Cargo
+        };
+        Err(failure.of(error))?;
+    }
+    if !bad_crates.is_empty() {
+        Err(Failure::Crates.of(anyhow!(
+            "crates in registry cache do not match the lockfile:\n  {}",
+            bad_crates
+                .iter()
+                .map(|b| format!(
+                    "{} {} ({}): {}",
+                    b.name,
+                    b.version,
+                    b.path.display(),
+                    b.problem
+                ))
+                .join("\n  ")
+        )))?;
+    }
+    if !failed.is_empty() {
+        Err(Failure::PartialFetch.of(anyhow!(
+            "could not fetch crates:\n  {}",
+            failed
+                .iter()
+                .map(|f| {
+                    let source = f.source.as_ref().expect("fetched packages have a source");
+                    format!("{} {} ({source}): {}", f.name, f.version, f.reason)
+                })
+                .join("\n  ")
+        )))?;
+    }
+    write_stamp()?;
+    Ok(ExitCode::SUCCESS)
+}
 
-    let mut registries = RegistryAliases::new();
-    let batches = batches::into_batches(packages)
-        .enumerate()
-        .map(|(i, batch)| (format!("batch{}", i + 1), batch))
-        .collect_vec();
-    for (batch_name, batch) in &batches {
-        cargo::run(
-            dir.as_ref(),
-            "init",
-            [batch_name.as_str(), "--name", batch_name, "--vcs", "none"],
-            cli.quiet,
-        )
-        .with_context(|| format!("failed to create sub-crate for {batch_name}"))?;
-        let child = dir.as_ref().as_ref().join(batch_name);
-        add_packages(
-            child,
-            batch.iter().map(|p| Dependency::Real(Box::new(p.clone()))),
-            &mut registries,
-        )
-        .with_context(|| format!("failed to add packages for {batch_name}"))?;
+/// Write `projects`, and the configuration of the `registries` they fetch from, into `dir`.
+fn write_projects(
+    dir: &Path,
+    projects: &[Project],
+    registries: &RegistryAliases,
+) -> Result<(), anyhow::Error> {
+    for project in projects {
+        write_project(dir, project)
+            .with_context(|| format!("failed to generate project {:?}", project.dir))?;
     }
-    add_packages(
-        dir.as_ref(),
-        batches
+    cargo_config_toml::write_registries(dir, registries).context("Failed to write registries")
+}
+
+/// Fetch `projects` again, as long as `fetched` failed, without the packages cargo, or a native
+/// download, failed on, and everything that depends on them, so that the rest is fetched.
+///
+/// `projects` are re-planned from `lockfiles` every round. Every round drops at least one package,
+/// so this ends. Returns the last fetch and the packages left out.
+fn keep_going(
+    dir: &Path,
+    mut fetched: Fetched,
+    projects: &mut Vec<Project>,
+    registries: &mut RegistryAliases,
+    plan: impl Fn(
+        &mut RegistryAliases,
+        &HashSet<dependency_graph::Identity>,
+    ) -> Result<Vec<Project>, anyhow::Error>,
+    cli: &CargoLockFetchCli,
+) -> Result<(Fetched, Vec<report::FailedPackage>), anyhow::Error> {
+    let mut dropped = HashSet::new();
+    let mut failed = vec![];
+    while !fetched.success() {
+        let blamed: HashMap<_, _> = fetched
+            .failures(projects)
+            .into_iter()
+            .map(|(p, reason)| (dependency_graph::identity(&(&p).into()), reason))
+            .collect();
+        if blamed.is_empty() {
+            break;
+        }
+        // Only fetched packages, as the generated crates may share identities with local ones.
+        let unfetchable: HashSet<_> = projects
             .iter()
-            .map(|(batch_name, _)| Dependency::BatchSubCrate(batch_name.clone())),
-        &mut registries,
-    )
-    .context("failed to add sub-crates as dependencies")?;
+            .flat_map(|project| {
+                dependency_graph::dependents_closure(
+                    &project.packages().cloned().collect_vec(),
+                    blamed.keys().cloned().collect(),
+                )
+            })
+            .collect();
+        failed.extend(
+            projects
+                .iter()
+                .flat_map(Project::packages)
+                .filter(|p| unfetchable.contains(&dependency_graph::identity(&(*p).into())))
+                .unique_by(|p| lockfiles::package_key(p))
+                .map(|p| report::FailedPackage {
+                    name: p.name.clone(),
+                    version: p.version.clone(),
+                    source: p.source.clone(),
+                    reason: blamed
+                        .get(&dependency_graph::identity(&p.into()))
+                        .cloned()
+                        .unwrap_or_else(|| {
+                            "depends on a crate that could not be fetched".to_string()
+                        }),
+                }),
+        );
+        dropped.extend(unfetchable);
+        *projects = plan(registries, &dropped)?;
+        write_projects(dir, projects, registries)?;
+        fetched = fetch(dir, projects, registries, cli)?;
+    }
+    Ok((fetched, failed))
+}
 
-    // Written after the manifests so that cargo sees a complete workspace: versions
-    // recorded in a Cargo.lock are exempt from cargo's yank filter, which lockfiles
-    // containing yanked versions rely on.
-    let synthesized = lockfile_synth::synthesize(resolve_version, &batches);
-    lockfile_synth::write_lockfile(dir.as_ref(), &synthesized)
-        .context("failed to write synthesized Cargo.lock")?;
+/// What fetching did.
+struct Fetched {
+    /// How cargo ran, `None` if it did not have to.
+    cargo: Option<cargo::Run>,
+    /// Packages that could not be downloaded with `--native`, and why. Cargo does not run then.
+    failed_downloads: Vec<(Package, String)>,
+}
 
-    let cargo_status = if let Some(ref vendor_dir) = cli.vendor_dir {
+impl Fetched {
+    fn success(&self) -> bool {
+        self.failed_downloads.is_empty() && self.cargo.as_ref().is_none_or(|r| r.status.success())
+    }
+
+    /// Packages of `projects` that could not be fetched, and why, as far as that can be told.
+    fn failures(&self, projects: &[Project]) -> Vec<(Package, String)> {
+        match &self.cargo {
+            Some(run) if !run.status.success() => projects
+                .iter()
+                .flat_map(|project| project.diagnose(&run.stderr))
+                .map(|(p, problem, _)| (p, problem.to_string()))
+                .collect(),
+            _ => self.failed_downloads.clone(),
+        }
+    }
+}
+
+/// Fetch, or vendor, the packages of the projects generated in `dir`.
+///
+/// With `--native`, downloadable packages are downloaded first, and cargo fetch only runs for
+/// projects with other packages. They are also vendored without cargo, unless there are other
+/// packages, in which case cargo vendor runs, finding them in its cache. Packages of registries
+/// that require authentication count as other packages, as only cargo has the credentials.
+fn fetch(
+    dir: &Path,
+    projects: &[Project],
+    registries: &RegistryAliases,
+    cli: &CargoLockFetchCli,
+) -> Result<Fetched, anyhow::Error> {
+    let fetched = |cargo| Fetched {
+        cargo,
+        failed_downloads: vec![],
+    };
+    if !cli.native {
+        return run_cargo(dir, &projects.iter().collect_vec(), registries, cli)
+            .map(Some)
+            .map(fetched);
+    }
+    let cargo_home = cargo_home::cargo_home()?;
+    let downloads = download::download(&cargo_home, projects.iter().flat_map(Project::packages))?;
+    if !cli.quiet {
+        eprintln!("downloaded crates: {}", downloads.downloaded);
+    }
+    // Crates of registries that require authentication are left to cargo, which has credentials.
+    let left_to_cargo: HashSet<_> = downloads
+        .left_to_cargo
+        .iter()
+        .map(|p| lockfiles::package_key(p))
+        .collect();
+    let downloaded = |p: &Package| {
+        download::is_downloadable(p) && !left_to_cargo.contains(&lockfiles::package_key(p))
+    };
+    if !downloads.failed.is_empty() {
+        return Ok(Fetched {
+            cargo: None,
+            failed_downloads: downloads
+                .failed
+                .into_iter()
+                .map(|(p, reason)| (p.clone(), reason))
+                .collect(),
+        });
+    }
+    if let Some(ref vendor_dir) = cli.vendor_dir {
+        let packages = || projects.iter().flat_map(Project::packages);
+        if packages().all(downloaded) {
+            let absolute_path = std::env::current_dir()
+                .context("Could not determine current directory")?
+                .join(vendor_dir);
+            let config =
+                vendor::vendor(&cargo_home, packages(), &absolute_path, cli.versioned_dirs)
+                    .context("failed to vendor packages")?;
+            if !cli.quiet {
+                print!("{config}");
+            }
+            return Ok(fetched(None));
+        }
+        warn!("not all crates were downloaded natively, vendoring with cargo");
+    }
+    let remaining = projects
+        .iter()
+        .filter(|p| cli.vendor_dir.is_some() || p.packages().any(|p| !downloaded(p)))
+        .collect_vec();
+    if remaining.is_empty() {
+        return Ok(fetched(None));
+    }
+    run_cargo(dir, &remaining, registries, cli)
+        .map(Some)
+        .map(fetched)
+}
+
+/// Run cargo fetch, or cargo vendor, on `projects` generated in `dir`.
+///
+/// What cargo printed to stderr is returned too, to tell what it failed on.
+fn run_cargo(
+    dir: &Path,
+    projects: &[&Project],
+    registries: &RegistryAliases,
+    cli: &CargoLockFetchCli,
+) -> Result<cargo::Run, anyhow::Error> {
+    let retries = cargo::Retries {
+        retries: cli.retries,
+        backoff: Duration::from_secs(cli.retry_backoff),
+        timeout: cli.timeout.map(Duration::from_secs),
+    };
+    let run = |cargo_cmd, args: Vec<&OsStr>| cargo::run(dir, cargo_cmd, args, cli.quiet, &retries);
+    let manifests = projects
+        .iter()
+        .map(|p| p.dir.join("Cargo.toml"))
+        .collect_vec();
+    let run = if let Some(ref vendor_dir) = cli.vendor_dir {
         let absolute_path = std::env::current_dir()
             .context("Could not determine current directory")?
             .join(vendor_dir);
         let absolute_path = absolute_path.to_str().ok_or_else(|| {
             anyhow!("cannot use path {absolute_path:?} as cargo argument: not utf8")
         })?;
-        cargo::run_passthrough(
-            dir.as_ref(),
+        let (manifest, others) = manifests
+            .split_first()
+            .expect("there should be at least one project");
+        run(
             "vendor",
-            ["--manifest-path", "Cargo.toml", absolute_path]
+            [OsStr::new("--manifest-path"), manifest.as_os_str()]
                 .into_iter()
-                .chain(cli.cargo_args.iter().map(AsRef::as_ref)),
-            cli.quiet,
+                .chain(
+                    others
+                        .iter()
+                        .flat_map(|m| [OsStr::new("--sync"), m.as_os_str()]),
+                )
+                // Local sources are only reachable through source replacement, which cargo vendor
+                // ignores by default.
+                .chain(
+                    registries
+                        .local_sources()
+                        .next()
+                        .map(|_| OsStr::new("--respect-source-config")),
+                )
+                .chain(cli.versioned_dirs.then_some(OsStr::new("--versioned-dirs")))
+                .chain(once(OsStr::new(absolute_path)))
+                .chain(cli.cargo_args.iter().map(AsRef::as_ref))
+                .collect(),
         )
         .context("failed to vendor packages")?
     } else {
-        cargo::run_passthrough(
-            dir.as_ref(),
-            "fetch",
-            ["--manifest-path", "Cargo.toml"]
-                .into_iter()
-                .chain(cli.cargo_args.iter().map(AsRef::as_ref)),
-            cli.quiet,
+        let mut last = None;
+        for manifest in &manifests {
+            let r = run(
+                "fetch",
+                [OsStr::new("--manifest-path"), manifest.as_os_str()]
+                    .into_iter()
+                    .chain(cli.cargo_args.iter().map(AsRef::as_ref))
+                    .collect(),
+            )
+            .context("failed to fetch packages")?;
+            let success = r.status.success();
+            last = Some(r);
+            if !success {
+                break;
+            }
+        }
+        last.expect("there should be at least one project")
+    };
+    Ok(run)
+}
+
+/// A cargo project which depends on all selected packages of a lockfile, not written to disk yet.
+struct Project {
+    /// Directory of the project, relative to the directory all projects are generated in.
+    dir: PathBuf,
+    /// Sub-crates of the project, each with the packages it depends on.
+    batches: Vec<(String, Vec<Package>)>,
+    /// `[dependencies]` of each sub-crate, in the same order as `batches`.
+    dependencies: Vec<toml_edit::Table>,
+    /// Cargo.lock of the project.
+    lockfile: Lockfile,
+    /// Local packages of the lockfile, which are not fetched.
+    local: Vec<Package>,
+    /// Packages added from `[[patch.unused]]` entries, which lockfiles record without a checksum.
+    unused_patches: HashSet<PackageKey>,
+    /// Whether the root crate depends on all packages itself, as the only batch, instead of
+    /// through sub-crates.
+    single_manifest: bool,
+}
+
+impl Project {
+    /// All packages fetched by the project.
+    fn packages(&self) -> impl Iterator<Item = &Package> {
+        self.batches.iter().flat_map(|(_, batch)| batch)
+    }
+
+    /// All packages fetched by the project, with their batches, keys in `[dependencies]` and
+    /// registry aliases.
+    fn keyed_packages(&self) -> Vec<(&str, String, &Package, Option<&str>)> {
+        self.batches
+            .iter()
+            .zip(&self.dependencies)
+            .flat_map(|((batch_name, batch), dependencies)| {
+                dependency_keys(batch)
+                    .into_iter()
+                    .zip(batch)
+                    .map(move |(key, p)| {
+                        let registry = dependencies
+                            .get(&key)
+                            .and_then(|entry| entry.get("registry"))
+                            .and_then(|registry| registry.as_str());
+                        (batch_name.as_str(), key, p, registry)
+                    })
+            })
+            .collect()
+    }
+
+    /// Packages of the project that cargo's `stderr` blames for its failure.
+    fn diagnose(&self, stderr: &str) -> Vec<(Package, cargo_failures::Problem, String)> {
+        let keyed = self.keyed_packages();
+        cargo_failures::diagnose(
+            stderr,
+            keyed.iter().map(|(_, k, p, r)| (k.as_str(), *p, *r)),
         )
-        .context("failed to fetch packages")?
+        .into_iter()
+        .map(|d| (d.package.clone(), d.problem.clone(), d.to_string()))
+        .collect()
+    }
+
+    /// Manifests of the project's crates, with their directories.
+    fn manifests(&self) -> Vec<(PathBuf, toml_edit::DocumentMut)> {
+        if self.single_manifest {
+            return self
+                .dependencies
+                .iter()
+                .map(|d| {
+                    (
+                        self.dir.clone(),
+                        cargo_toml::manifest(ROOT_CRATE, d.clone()),
+                    )
+                })
+                .collect();
+        }
+        let root = cargo_toml::manifest(ROOT_CRATE, sub_crate_dependencies(&self.batches));
+        self.batches
+            .iter()
+            .zip(&self.dependencies)
+            .map(|((batch_name, _), d)| {
+                let manifest = cargo_toml::manifest(batch_name, d.clone());
+                (self.dir.join(batch_name), manifest)
+            })
+            .chain(once((self.dir.clone(), root)))
+            .collect()
+    }
+}
+
+/// Plan a cargo project for each of `lockfiles`, leaving out the `dropped` packages entirely.
+fn plan_projects(
+    lockfiles: &[Lockfile],
+    master_branches: &HashSet<SourceId>,
+    registries: &mut RegistryAliases,
+    previous: &HashSet<PackageKey>,
+    dropped: &HashSet<dependency_graph::Identity>,
+    cli: &CargoLockFetchCli,
+) -> Result<Vec<Project>, anyhow::Error> {
+    // Lockfiles that could not be merged are fetched by separate projects, next to each other, so
+    // that cargo can be run for each of them with the same registries configuration.
+    let project_dirs = if lockfiles.len() == 1 {
+        vec![PathBuf::from(".")]
+    } else {
+        (1..=lockfiles.len())
+            .map(|i| PathBuf::from(format!("project{i}")))
+            .collect_vec()
     };
-    let cargo_code = cargo_status
-        .code()
-        .map(|c| (c as u8).into())
-        .unwrap_or(ExitCode::FAILURE);
-    Ok(cargo_code)
+    project_dirs
+        .into_iter()
+        .zip(lockfiles)
+        .map(|(project_dir, lockfile)| {
+            plan_project(
+                &project_dir,
+                lockfile.clone(),
+                master_branches,
+                registries,
+                previous,
+                dropped,
+                cli,
+            )
+            .with_context(|| format!("failed to plan project {project_dir:?}"))
+        })
+        .try_collect()
 }
 
-fn add_packages(
-    dir: impl AsRef<Path>,
-    deps: impl IntoIterator<Item = Dependency>,
+/// Plan a cargo project in `project` which depends on all selected packages of `lockfile`.
+fn plan_project(
+    project: &Path,
+    lockfile: Lockfile,
+    master_branches: &HashSet<SourceId>,
     registries: &mut RegistryAliases,
-) -> Result<(), anyhow::Error> {
-    let deps = deps
+    previous: &HashSet<PackageKey>,
+    dropped: &HashSet<dependency_graph::Identity>,
+    cli: &CargoLockFetchCli,
+) -> Result<Project, anyhow::Error> {
+    let Lockfile {
+        version: resolve_version,
+        mut packages,
+        metadata,
+        patch,
+        ..
+    } = lockfile;
+    let mut patches = HashSet::new();
+    if cli.unused_patches {
+        let unused = unused_patches(patch, &packages);
+        patches.extend(unused.iter().map(lockfiles::package_key));
+        packages.extend(unused);
+    }
+    // Local packages cannot be fetched, but their dependencies are packages of the lockfile too.
+    let (local, packages): (Vec<_>, Vec<_>) = resolve_replacements(packages)
         .into_iter()
-        .map(|dep| match dep {
-            Dependency::Real(p) => p,
-            Dependency::BatchSubCrate(name) => Box::new(Package {
-                name: Name::from_str(&name).expect("sub-crate's name should be correct"),
-                version: Version::new(0, 0, 0),
-                source: None,
-                checksum: None,
-                dependencies: vec![],
-                replace: None,
-            }),
+        .filter(|p| !dropped.contains(&dependency_graph::identity(&p.into())))
+        .partition(local_packages::is_local);
+    let packages = packages
+        .into_iter()
+        .filter(|p| package_filter::is_selected(&cli.include, &cli.exclude, p));
+    // Packages fetched before still lock the versions of transitive dependencies of new ones, so
+    // that cargo does not resolve them anew.
+    let (packages, fetched_before): (Vec<_>, Vec<_>) =
+        packages.partition(|p| !previous.contains(&lockfiles::package_key(p)));
+
+    // Versions of the same crate are either spread across sub-crates, or renamed dependencies of
+    // the root crate.
+    let batches = if cli.single_manifest {
+        vec![(ROOT_CRATE.to_string(), packages)]
+    } else {
+        batches::into_batches(packages.into_iter().map(|p| (p.name.clone(), p)).collect())
+            .enumerate()
+            .map(|(i, batch)| (format!("batch{}", i + 1), batch))
+            .collect_vec()
+    };
+    let mut errors = vec![];
+    let dependencies = batches
+        .iter()
+        .map(|(_, batch)| {
+            dependency_entries(batch, master_branches, registries).unwrap_or_else(|e| {
+                errors.extend(e);
+                toml_edit::Table::new()
+            })
         })
         .collect_vec();
+    if !errors.is_empty() {
+        Err(Failure::UnsupportedSource.of(anyhow!(
+            "unsupported crate sources:\n  {}",
+            errors.iter().join("\n  ")
+        )))?;
+    }
+
+    let lockfile = lockfile_synth::synthesize(
+        resolve_version,
+        &metadata,
+        &batches,
+        cli.single_manifest,
+        &fetched_before,
+    );
+    Ok(Project {
+        dir: project.to_owned(),
+        batches,
+        dependencies,
+        lockfile,
+        local,
+        unused_patches: patches,
+        single_manifest: cli.single_manifest,
+    })
+}
+
+/// Write `project`'s manifests and lockfile into `dir`.
+fn write_project(dir: &Path, project: &Project) -> Result<(), anyhow::Error> {
+    for (crate_dir, manifest) in project.manifests() {
+        cargo_toml::write_package(dir.join(&crate_dir), &manifest)
+            .with_context(|| format!("failed to create crate in {crate_dir:?}"))?;
+    }
+    let dir = dir.join(&project.dir);
+
+    // Written after the manifests so that cargo sees a complete workspace: versions
+    // recorded in a Cargo.lock are exempt from cargo's yank filter, which lockfiles
+    // containing yanked versions rely on.
+    lockfile_synth::write_lockfile(&dir, &project.lockfile)
+        .context("failed to write synthesized Cargo.lock")
+}
+
+/// Print the files that would be generated for `projects`, instead of running cargo on them.
+fn print_plan(projects: &[Project], registries: &RegistryAliases) {
+    for project in projects {
+        for (crate_dir, manifest) in project.manifests() {
+            let path = crate_dir.join("Cargo.toml");
+            println!("# {}\n{manifest}", path.display());
+        }
+        let path = project.dir.join("Cargo.lock");
+        println!("# {}\n{}", path.display(), project.lockfile.to_string());
+    }
+    let config = cargo_config_toml::registries_config(registries);
+    println!("# .cargo/config.toml\n{config}");
+}
 
-    let entries = deps.iter().map(|p| {
-        let name = p.name.as_str();
-        let spec = match &p.source {
-            // Our own dummy sub-crate for a batch of crates, because original pakaages without
-            // source are filtered out just after parsing Cargo.toml.
-            None => Ok(toml_edit::Table::from_iter(once(("path", p.name.as_str())))),
-            // Any other original dependency not from default registry
-            Some(source) => {
-                match source_to_dependency_entry(p.name.as_str(), source, &p.version.to_string(), registries) {
-                    Ok(spec) => Ok(spec),
-                    Err(error @ SourceError::Unsupported(_)) => {
-                        error!(error:err, dependency_crate:serde = p; "unsupported crate source");
-                        Err(error).with_context(|| format!("failed to add crate {name}"))?
-                    }
+/// Describe all packages fetched by `projects`, checking if they are in `cargo_home` already.
+fn packages_report(projects: &[Project], cargo_home: &Path) -> Vec<report::PackageReport> {
+    projects
+        .iter()
+        .flat_map(|project| {
+            project
+                .keyed_packages()
+                .into_iter()
+                .map(|(batch_name, _, p, registry)| report::PackageReport {
+                    name: p.name.clone(),
+                    version: p.version.clone(),
+                    source: p.source.clone(),
+                    checksum: p.checksum.clone(),
+                    project: project.dir.display().to_string(),
+                    batch: batch_name.to_string(),
+                    registry: registry.map(str::to_owned),
+                    cached: cargo_home::is_cached(cargo_home, p),
+                })
+        })
+        .collect()
+}
+
+/// `[dependencies]` of a project's root crate, i.e. its sub-crates.
+fn sub_crate_dependencies(batches: &[(String, Vec<Package>)]) -> toml_edit::Table {
+    toml_edit::Table::from_iter(batches.iter().map(|(batch_name, _)| {
+        (
+            batch_name,
+            toml_edit::Table::from_iter(once(("path", batch_name))),
+        )
+    }))
+}
+
+/// Tell why fetching `package` may not give the same contents every time, if it may not.
+///
+/// Lockfiles never record checksums of `unused_patches`, cargo checks those against the checksums
+/// of the registry index instead, so they are not blamed for missing one.
+fn non_reproducible(
+    package: &Package,
+    unused_patches: &HashSet<PackageKey>,
+) -> Option<&'static str> {
+    let source = package.source.as_ref()?;
+    match source.kind() {
+        SourceKind::Git(GitReference::Branch(_)) if source.precise().is_none() => {
+            Some("git branch without a precise revision")
+        }
+        SourceKind::Registry | SourceKind::SparseRegistry
+            if package.checksum.is_none()
+                && !unused_patches.contains(&lockfiles::package_key(package)) =>
+        {
+            Some("registry crate without a checksum")
+        }
+        SourceKind::Path => Some("path source"),
+        _ => None,
+    }
+}
+
+/// Packages of `[[patch.unused]]` entries that are not among `packages` already.
+///
+/// Such patches are inactive in the resolved graph, but become active, and so need to be
+/// available, when the original project is built for another target or feature set.
+fn unused_patches(patch: Patch, packages: &[Package]) -> Vec<Package> {
+    patch
+        .unused
+        .into_iter()
+        .filter(|d| {
+            !packages
+                .iter()
+                .any(|p| p.name == d.name && p.version == d.version && p.source == d.source)
+        })
+        .unique()
+        .map(|d| Package {
+            name: d.name,
+            version: d.version,
+            source: d.source,
+            checksum: None,
+            dependencies: vec![],
+            replace: None,
+        })
+        .collect()
+}
+
+/// Substitute packages replaced with `[replace]` by their replacements.
+///
+/// The original packages are never built, so only the replacement needs to be fetched. Usually the
+/// replacement is in the lockfile already, if it is not, it is reconstructed from the reference.
+fn resolve_replacements(packages: Vec<Package>) -> Vec<Package> {
+    let present: HashSet<_> = packages
+        .iter()
+        .map(|p| dependency_graph::identity(&p.into()))
+        .collect();
+    let replacements = packages
+        .iter()
+        .filter_map(|p| p.replace.as_ref())
+        .filter(|r| !present.contains(&dependency_graph::identity(r)))
+        .unique()
+        .map(|r| Package {
+            name: r.name.clone(),
+            version: r.version.clone(),
+            source: r.source.clone(),
+            checksum: None,
+            dependencies: vec![],
+            replace: None,
+        })
+        .collect_vec();
+    packages
+        .into_iter()
+        .filter(|p| p.replace.is_none())
+        .chain(replacements)
+        .collect()
+}
+
+/// Build `[dependencies]` entries for `packages`, or describe every package whose source is not
+/// supported.
+fn dependency_entries(
+    packages: &[Package],
+    master_branches: &HashSet<SourceId>,
+    registries: &mut RegistryAliases,
+) -> Result<toml_edit::Table, Vec<String>> {
+    let (entries, errors): (Vec<_>, Vec<_>) = packages
+        .iter()
+        .zip(dependency_keys(packages))
+        .map(|(p, key)| {
+            let source = p
+                .source
+                .as_ref()
+                .expect("batched packages should have a source");
+            let version = p.version.to_string();
+            source_to_dependency_entry(
+                p.name.as_str(),
+                source,
+                &version,
+                master_branches,
+                registries,
+            )
+            .map(|mut entry| {
+                if key != p.name.as_str() {
+                    entry.insert("package", toml_edit::value(p.name.as_str()));
+                }
+                (key, entry)
+            })
+            .map_err(|error| {
+                error!(error:err, dependency_crate:serde = p; "unsupported crate source");
+                format!("{} {} ({source}): {error}", p.name, p.version)
+            })
+        })
+        .partition_result();
+    if errors.is_empty() {
+        Ok(toml_edit::Table::from_iter(entries))
+    } else {
+        Err(errors)
+    }
+}
+
+/// Keys of `packages` in `[dependencies]`: their names, or, for crates with more than one version,
+/// names of renamed dependencies made of the crate name and version.
+fn dependency_keys(packages: &[Package]) -> Vec<String> {
+    let counts = packages.iter().counts_by(|p| &p.name);
+    let mut taken = HashSet::new();
+    packages
+        .iter()
+        .map(|p| {
+            let base = if counts[&p.name] == 1 {
+                p.name.to_string()
+            } else {
+                let version = p.version.to_string();
+                let version = version.replace(|c: char| !c.is_ascii_alphanumeric(), "_");
+                format!("{}-{version}", p.name)
+            };
+            // The same version may come from different sources.
+            let mut key = base.clone();
+            for i in 2.. {
+                if taken.insert(key.clone()) {
+                    break;
                 }
+                key = format!("{base}-{i}");
             }
-        };
-        spec.map(|s| (name, s))
-    }).try_collect::<_, _, anyhow::Error>()?;
-    cargo_config_toml::write_registries(&dir, registries).context("Failed to write registries")?;
-    cargo_toml::write_dependencies(&dir, entries).context("Failed to write dependencies")?;
-    Ok(())
+            key
+        })
+        .collect()
 }
 
 fn source_to_dependency_entry(
     name: &str,
     source: &SourceId,
     version: &str,
+    master_branches: &HashSet<SourceId>,
     registries: &mut RegistryAliases,
 ) -> Result<toml_edit::Table, SourceError> {
     use toml_edit::{Table, value as v};
@@ -190,21 +1020,28 @@ fn source_to_dependency_entry(
     let uri = source.url().as_str();
     let mut entry = match source.kind() {
         SourceKind::Git(git_reference) => {
-            let (ref_type, ref_val) = match (source.precise(), git_reference) {
-                (Some(precise), _) => ("rev", precise),
-                (None, GitReference::Tag(t)) => ("tag", t.as_str()),
-                (None, GitReference::Branch(branch)) => {
+            let reference = match (source.precise(), git_reference) {
+                // The precise revision is the commit the original lockfile was resolved to.
+                (Some(precise), _) => Some(("rev", precise)),
+                (None, GitReference::Tag(t)) => Some(("tag", t.as_str())),
+                (None, GitReference::Rev(r)) => Some(("rev", r.as_str())),
+                (None, reference @ GitReference::Branch(branch)) => {
                     warn!(
                         name, source:serde, branch;
                         "adding crate with no precise rev and branch source, this is not reproducible!"
                     );
-                    ("branch", uri)
+                    // Sources without a reference are parsed as the "master" branch as well, but
+                    // stand for the repository's default branch, so they are left implicit.
+                    (reference.pretty_ref(false).is_some() || master_branches.contains(source))
+                        .then_some(("branch", branch.as_str()))
                 }
-                (None, GitReference::Rev(r)) => ("rev", r.as_str()),
             };
-            Table::from_iter([("git", v(uri)), (ref_type, v(ref_val))])
+            let mut entry = Table::from_iter([("git", v(uri))]);
+            if let Some((ref_type, ref_val)) = reference {
+                entry.insert(ref_type, v(ref_val));
+            }
+            entry
         }
-        SourceKind::Path => Table::from_iter([("path", v(uri))]),
         // The default registry is left implicit so that cargo resolves it exactly like the
         // original project would, honoring the default protocol and any source replacement,
         // and therefore populates the same registry cache.
@@ -225,6 +1062,27 @@ fn source_to_dependency_entry(
                 ("registry", v(registries.get_alias(registry_uri))),
             ])
         }
+        // Local registries and directories cannot be depended upon, so the dependency is on a
+        // registry of the same URL instead, which .cargo/config.toml replaces with the local
+        // source.
+        kind @ (SourceKind::LocalRegistry | SourceKind::Directory) => {
+            let path = source
+                .url()
+                .to_file_path()
+                .map_err(|()| SourceError::Unsupported(kind.clone()))?;
+            let local = if *kind == SourceKind::LocalRegistry {
+                LocalSource::LocalRegistry(path)
+            } else {
+                LocalSource::Directory(path)
+            };
+            Table::from_iter([
+                ("version", v(format!("={version}"))),
+                (
+                    "registry",
+                    v(registries.get_local_alias(uri.to_string(), local)),
+                ),
+            ])
+        }
         kind => return Err(SourceError::Unsupported(kind.clone())),
     };
     entry["default-features"] = v(false);
@@ -237,24 +1095,89 @@ pub enum SourceError {
     Unsupported(SourceKind),
 }
 
-enum Dependency {
-    Real(Box<Package>),
-    BatchSubCrate(String),
+/// Kinds of failures, which scripts can tell apart by the exit code.
+///
+/// The exit codes are a part of the CLI interface, so they must not change.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Failure {
+    /// With `--keep-going`, some crates could not be fetched, but all others were.
+    PartialFetch,
+    /// A lockfile could not be found or read, or does not match the command line.
+    InvalidLockfile,
+    /// Crates come from sources which are not supported, or not reproducible with `--strict`.
+    UnsupportedSource,
+    /// Crates could not be downloaded, or cargo failed because of the network or timed out.
+    Network,
+    /// Cargo failed for another reason.
+    Cargo,
+    /// Crates are missing with `--check`, or do not match the lockfile with `--verify`.
+    Crates,
+}
+
+impl Failure {
+    /// Exit code for all other errors, like I/O errors.
+    pub const OTHER_EXIT_CODE: u8 = 1;
+
+    pub fn exit_code(self) -> u8 {
+        match self {
+            Failure::PartialFetch => 3,
+            Failure::InvalidLockfile => 4,
+            Failure::UnsupportedSource => 5,
+            Failure::Network => 6,
+            Failure::Cargo => 7,
+            Failure::Crates => 8,
+        }
+    }
+
+    /// Mark `error` as this kind of failure.
+    pub fn of(self, error: impl Into<anyhow::Error>) -> anyhow::Error {
+        FailureError {
+            failure: self,
+            error: error.into(),
+        }
+        .into()
+    }
+}
+
+/// An error marked as a kind of failure, otherwise the same as the error.
+#[derive(Debug)]
+pub struct FailureError {
+    pub failure: Failure,
+    error: anyhow::Error,
+}
+
+impl std::fmt::Display for FailureError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.error.fmt(f)
+    }
+}
+
+impl std::error::Error for FailureError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.error.source()
+    }
 }
 
 #[cfg(test)]
 mod test {
-    use cargo_lock::SourceId;
+    use std::{collections::HashSet, str::FromStr as _};
+
+    use cargo_lock::{Lockfile, SourceId};
     use itertools::Itertools as _;
 
-    use super::source_to_dependency_entry;
-    use crate::registry_aliases::RegistryAliases;
+    use super::{
+        Failure, FailureError, dependency_entries, non_reproducible, resolve_replacements,
+        source_to_dependency_entry, unused_patches,
+    };
+    use crate::{lockfiles, registry_aliases::RegistryAliases};
 
     fn entry_and_registries(source_url: &str) -> (toml_edit::Table, Vec<(String, String)>) {
         let source = SourceId::from_url(source_url).expect("source url should parse");
+        let master_branches = lockfiles::master_branches(&format!("source = \"{source_url}\""));
         let mut registries = RegistryAliases::new();
-        let entry = source_to_dependency_entry("foo", &source, "1.2.3", &mut registries)
-            .expect("registry source should be supported");
+        let entry =
+            source_to_dependency_entry("foo", &source, "1.2.3", &master_branches, &mut registries)
+                .expect("source should be supported");
         let registries = registries
             .iter()
             .map(|(a, u)| (a.to_owned(), u.to_owned()))
@@ -305,4 +1228,433 @@ mod test {
             )]
         );
     }
+
+    fn git_entry(source_url: &str) -> Vec<(String, String)> {
+        let (entry, registries) = entry_and_registries(source_url);
+        assert_eq!(registries, vec![]);
+        entry
+            .iter()
+            .filter(|(key, _)| *key != "default-features")
+            .map(|(key, value)| {
+                (
+                    key.to_string(),
+                    value.as_str().unwrap_or_default().to_string(),
+                )
+            })
+            .collect_vec()
+    }
+
+    fn pairs(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect_vec()
+    }
+
+    const PRECISE: &str = "0123456789abcdef0123456789abcdef01234567";
+
+    #[test]
+    fn precise_git_revision_takes_precedence_over_reference() {
+        for reference in ["", "?branch=main", "?tag=v1.0.0", "?rev=0123456"] {
+            assert_eq!(
+                git_entry(&format!(
+                    "git+https://example.com/repo{reference}#{PRECISE}"
+                )),
+                pairs(&[("git", "https://example.com/repo"), ("rev", PRECISE)]),
+                "reference {reference:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn git_branch_without_precise_revision() {
+        assert_eq!(
+            git_entry("git+https://example.com/repo?branch=main"),
+            pairs(&[("git", "https://example.com/repo"), ("branch", "main")])
+        );
+    }
+
+    #[test]
+    fn git_tag_without_precise_revision() {
+        assert_eq!(
+            git_entry("git+https://example.com/repo?tag=v1.0.0"),
+            pairs(&[("git", "https://example.com/repo"), ("tag", "v1.0.0")])
+        );
+    }
+
+    #[test]
+    fn git_rev_without_precise_revision() {
+        assert_eq!(
+            git_entry("git+https://example.com/repo?rev=0123456"),
+            pairs(&[("git", "https://example.com/repo"), ("rev", "0123456")])
+        );
+    }
+
+    #[test]
+    fn git_default_branch_has_no_reference() {
+        assert_eq!(
+            git_entry("git+https://example.com/repo"),
+            pairs(&[("git", "https://example.com/repo")])
+        );
+    }
+
+    #[test]
+    fn git_explicit_master_branch_is_kept() {
+        assert_eq!(
+            git_entry("git+https://example.com/repo?branch=master"),
+            pairs(&[("git", "https://example.com/repo"), ("branch", "master")])
+        );
+    }
+
+    #[test]
+    fn git_query_parameters_are_decoded_and_not_kept_in_url() {
+        assert_eq!(
+            git_entry("git+https://example.com/repo.git?branch=feature%2Fx&depth=1"),
+            pairs(&[
+                ("git", "https://example.com/repo.git"),
+                ("branch", "feature/x")
+            ])
+        );
+        // Older lockfiles spell branches as `ref`.
+        assert_eq!(
+            git_entry("git+https://example.com/repo?ref=dev"),
+            pairs(&[("git", "https://example.com/repo"), ("branch", "dev")])
+        );
+    }
+
+    #[test]
+    fn unused_patches_become_packages_unless_already_present() {
+        let lockfile = Lockfile::from_str(indoc::indoc! {r#"
+            version = 4
+
+            [[package]]
+            name = "memchr"
+            version = "2.8.2"
+            source = "git+https://example.com/memchr#0123456789abcdef0123456789abcdef01234567"
+
+            [[patch.unused]]
+            name = "memchr"
+            version = "2.8.2"
+            source = "git+https://example.com/memchr#0123456789abcdef0123456789abcdef01234567"
+
+            [[patch.unused]]
+            name = "syn"
+            version = "2.0.118"
+            source = "git+https://example.com/syn#89abcdef0123456789abcdef0123456789abcdef"
+        "#})
+        .expect("fixture should parse");
+
+        let unused = unused_patches(lockfile.patch, &lockfile.packages);
+
+        let [syn] = &unused[..] else {
+            panic!("exactly one new package expected");
+        };
+        assert_eq!(syn.name.as_str(), "syn");
+        assert_eq!(syn.version.to_string(), "2.0.118");
+        assert!(syn.source.as_ref().is_some_and(SourceId::is_git));
+    }
+
+    #[test]
+    fn unused_registry_patches_are_reproducible_without_checksum() {
+        let lockfile = Lockfile::from_str(indoc::indoc! {r#"
+            [[package]]
+            name = "app"
+            version = "0.1.0"
+
+            [[patch.unused]]
+            name = "itoa"
+            version = "1.0.11"
+            source = "registry+https://github.com/rust-lang/crates.io-index"
+        "#})
+        .expect("fixture should parse");
+
+        let unused = unused_patches(lockfile.patch, &lockfile.packages);
+        let keys: HashSet<_> = unused.iter().map(lockfiles::package_key).collect();
+
+        let [itoa] = &unused[..] else {
+            panic!("exactly one new package expected");
+        };
+        assert_eq!(non_reproducible(itoa, &keys), None);
+        assert_eq!(
+            non_reproducible(itoa, &HashSet::new()),
+            Some("registry crate without a checksum")
+        );
+    }
+
+    #[test]
+    fn replaced_packages_are_substituted_by_replacements() {
+        let lockfile = Lockfile::from_str(indoc::indoc! {r#"
+            [[package]]
+            name = "itoa"
+            version = "1.0.11"
+            source = "registry+https://github.com/rust-lang/crates.io-index"
+            replace = "itoa 1.0.11 (git+https://example.com/itoa#0123456789abcdef0123456789abcdef01234567)"
+
+            [[package]]
+            name = "itoa"
+            version = "1.0.11"
+            source = "git+https://example.com/itoa#0123456789abcdef0123456789abcdef01234567"
+
+            [[package]]
+            name = "memchr"
+            version = "2.8.2"
+            source = "registry+https://github.com/rust-lang/crates.io-index"
+            replace = "memchr 2.8.2 (git+https://example.com/memchr?rev=89abcdef#89abcdef0123456789abcdef0123456789abcdef)"
+        "#})
+        .expect("fixture should parse");
+
+        let packages = resolve_replacements(lockfile.packages);
+
+        assert_eq!(
+            packages
+                .iter()
+                .map(|p| {
+                    let source = p.source.as_ref().expect("fixture packages have sources");
+                    let entry = source_to_dependency_entry(
+                        p.name.as_str(),
+                        source,
+                        &p.version.to_string(),
+                        &HashSet::new(),
+                        &mut RegistryAliases::new(),
+                    )
+                    .expect("git source should be supported");
+                    (p.name.to_string(), entry.to_string())
+                })
+                .sorted()
+                .collect_vec(),
+            vec![
+                (
+                    "itoa".to_string(),
+                    toml_edit::Table::from_iter([
+                        ("git", toml_edit::value("https://example.com/itoa")),
+                        (
+                            "rev",
+                            toml_edit::value("0123456789abcdef0123456789abcdef01234567")
+                        ),
+                        ("default-features", toml_edit::value(false)),
+                    ])
+                    .to_string()
+                ),
+                (
+                    "memchr".to_string(),
+                    toml_edit::Table::from_iter([
+                        ("git", toml_edit::value("https://example.com/memchr")),
+                        (
+                            "rev",
+                            toml_edit::value("89abcdef0123456789abcdef0123456789abcdef")
+                        ),
+                        ("default-features", toml_edit::value(false)),
+                    ])
+                    .to_string()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn all_unsupported_sources_are_reported() {
+        let package = |name: &str, source| cargo_lock::Package {
+            name: name.parse().expect("name should be valid"),
+            version: "1.0.0".parse().expect("version should be valid"),
+            source: Some(source),
+            checksum: None,
+            dependencies: vec![],
+            replace: None,
+        };
+        let packages = [
+            package(
+                "itoa",
+                SourceId::for_path("/srv/itoa".as_ref()).expect("path source should be valid"),
+            ),
+            package(
+                "memchr",
+                SourceId::from_url("registry+https://github.com/rust-lang/crates.io-index")
+                    .expect("source url should parse"),
+            ),
+            package(
+                "syn",
+                SourceId::for_path("/srv/syn".as_ref()).expect("path source should be valid"),
+            ),
+        ];
+
+        let errors = dependency_entries(&packages, &HashSet::new(), &mut RegistryAliases::new())
+            .expect_err("path sources should be unsupported");
+
+        assert_eq!(errors.len(), 2);
+        assert!(errors[0].starts_with("itoa 1.0.0"));
+        assert!(errors[1].starts_with("syn 1.0.0"));
+    }
+
+    #[test]
+    fn many_versions_of_a_crate_are_renamed_dependencies() {
+        let lockfile = Lockfile::from_str(indoc::indoc! {r#"
+            [[package]]
+            name = "syn"
+            version = "1.0.109"
+            source = "registry+https://github.com/rust-lang/crates.io-index"
+
+            [[package]]
+            name = "syn"
+            version = "2.0.118"
+            source = "registry+https://github.com/rust-lang/crates.io-index"
+
+            [[package]]
+            name = "syn"
+            version = "2.0.118"
+            source = "git+https://github.com/dtolnay/syn?rev=1234567#1234567"
+
+            [[package]]
+            name = "itoa"
+            version = "1.0.18"
+            source = "registry+https://github.com/rust-lang/crates.io-index"
+        "#})
+        .expect("fixture should parse");
+
+        let entries = dependency_entries(
+            &lockfile.packages,
+            &HashSet::new(),
+            &mut RegistryAliases::new(),
+        )
+        .expect("sources should be supported");
+
+        assert_eq!(
+            entries
+                .iter()
+                .map(|(key, entry)| (key, entry.get("package").and_then(|p| p.as_str())))
+                .collect_vec(),
+            vec![
+                ("syn-1_0_109", Some("syn")),
+                ("syn-2_0_118", Some("syn")),
+                ("syn-2_0_118-2", Some("syn")),
+                ("itoa", None),
+            ]
+        );
+    }
+
+    #[test]
+    fn local_sources_replace_registries_of_the_same_url() {
+        let mut registries = RegistryAliases::new();
+        let local_registry = SourceId::for_local_registry("/srv/registry".as_ref())
+            .expect("local registry source should be valid");
+        let directory = SourceId::for_directory("/srv/vendor".as_ref())
+            .expect("directory source should be valid");
+
+        let a = source_to_dependency_entry(
+            "itoa",
+            &local_registry,
+            "1.0.0",
+            &HashSet::new(),
+            &mut registries,
+        )
+        .expect("local registry source should be supported");
+        let b = source_to_dependency_entry(
+            "syn",
+            &directory,
+            "2.0.0",
+            &HashSet::new(),
+            &mut registries,
+        )
+        .expect("directory source should be supported");
+
+        assert_eq!(a["version"].as_str(), Some("=1.0.0"));
+        assert_eq!(a["registry"].as_str(), Some("reg1"));
+        assert_eq!(b["registry"].as_str(), Some("reg2"));
+        assert_eq!(
+            crate::cargo_config_toml::registries_config(&registries).to_string(),
+            indoc::indoc! {r#"
+                [registries]
+
+                [registries.reg1]
+                index = "file:///srv/registry"
+
+                [registries.reg2]
+                index = "file:///srv/vendor"
+
+                [source]
+
+                [source.reg1]
+                registry = "file:///srv/registry"
+                replace-with = "reg1-local"
+
+                [source.reg1-local]
+                local-registry = "/srv/registry"
+
+                [source.reg2]
+                registry = "file:///srv/vendor"
+                replace-with = "reg2-local"
+
+                [source.reg2-local]
+                directory = "/srv/vendor"
+            "#}
+        );
+    }
+
+    #[test]
+    fn finds_non_reproducible_packages() {
+        let lockfile = Lockfile::from_str(indoc::indoc! {r#"
+            [[package]]
+            name = "app"
+            version = "0.1.0"
+
+            [[package]]
+            name = "itoa"
+            version = "1.0.11"
+            source = "git+https://example.com/itoa?branch=main"
+
+            [[package]]
+            name = "memchr"
+            version = "2.8.0"
+            source = "git+https://example.com/memchr?branch=main#89abcdef0123456789abcdef0123456789abcdef"
+
+            [[package]]
+            name = "serde"
+            version = "1.0.200"
+            source = "registry+https://github.com/rust-lang/crates.io-index"
+
+            [[package]]
+            name = "syn"
+            version = "2.0.118"
+            source = "registry+https://github.com/rust-lang/crates.io-index"
+            checksum = "cafebabecafebabecafebabecafebabecafebabecafebabecafebabecafebabe"
+
+            [[package]]
+            name = "utils"
+            version = "0.1.0"
+            source = "path+file:///srv/utils"
+        "#})
+        .expect("fixture should parse");
+
+        assert_eq!(
+            lockfile
+                .packages
+                .iter()
+                .map(|p| (p.name.as_str(), non_reproducible(p, &HashSet::new())))
+                .collect_vec(),
+            vec![
+                ("app", None),
+                ("itoa", Some("git branch without a precise revision")),
+                ("memchr", None),
+                ("serde", Some("registry crate without a checksum")),
+                ("syn", None),
+                ("utils", Some("path source")),
+            ]
+        );
+    }
+
+    #[test]
+    fn failures_are_told_apart_by_exit_code() {
+        let failure = Failure::Network.of(anyhow::anyhow!("timed out"));
+        let failure = failure.context("failed to fetch packages");
+
+        let code = failure
+            .chain()
+            .find_map(|e| e.downcast_ref::<FailureError>())
+            .map(|e| e.failure.exit_code());
+
+        assert_eq!(code, Some(6));
+        assert_eq!(
+            format!("{failure:#}"),
+            "failed to fetch packages: timed out"
+        );
+    }
 }
@@ -1,51 +1,65 @@
-use std::{
-    fs::OpenOptions,
-    io::{Read, Write as _},
-    path::Path,
-    str::FromStr as _,
-};
+use std::{iter::once, path::Path};
 
 use anyhow::Context;
-use toml_edit::DocumentMut;
+use toml_edit::{DocumentMut, Table};
 
-pub fn write_dependencies(
-    dir: impl AsRef<Path>,
-    entries: Vec<(&str, toml_edit::Table)>,
-) -> Result<(), anyhow::Error> {
-    use toml_edit::Table;
+/// Manifest of a generated library package `name`, which depends on `dependencies`.
+pub fn manifest(name: &str, dependencies: Table) -> DocumentMut {
+    let package = Table::from_iter([
+        ("name", name),
+        ("version", "0.1.0"),
+        // Supported by all cargo versions that can read the lockfiles this tool writes.
+        ("edition", "2021"),
+    ]);
+    let mut manifest = Table::from_iter(once(("package", package)));
+    manifest.insert("dependencies", dependencies.into());
+    manifest.into()
+}
 
-    let mut ro = OpenOptions::new()
-        .read(true)
-        .open(dir.as_ref().join("Cargo.toml"))
-        .with_context(|| format!("Failed to open Cargo.toml {:?}", dir.as_ref().as_os_str()))?;
-    let mut cargo = String::new();
-    ro.read_to_string(&mut cargo).with_context(|| {
+/// Write a library package with `manifest` and an empty `src/lib.rs` to `dir`.
+pub fn write_package(dir: impl AsRef<Path>, manifest: &DocumentMut) -> Result<(), anyhow::Error> {
+    let src = dir.as_ref().join("src");
+    std::fs::create_dir_all(&src)
+        .with_context(|| format!("Failed to create {:?}", src.as_os_str()))?;
+    std::fs::write(src.join("lib.rs"), "")
+        .with_context(|| format!("Failed to write lib.rs in {:?}", src.as_os_str()))?;
+    std::fs::write(dir.as_ref().join("Cargo.toml"), manifest.to_string()).with_context(|| {
         format!(
-            "Could not read from Cargo.toml in {:?}",
+            "Failed to write Cargo.toml in {:?}",
             dir.as_ref().as_os_str()
         )
-    })?;
-
-    let mut cargo =
-        DocumentMut::from_str(&cargo).expect("Cargo.toml created by cargo should be valid TOML");
-    cargo["dependencies"] = Table::from_iter(entries).into();
-
-    let mut file = OpenOptions::new()
-        .write(true)
-        .truncate(true)
-        .open(dir.as_ref().join("Cargo.toml"))
-        .with_context(|| {
-            format!(
-                "Failed to open Cargo.toml in {:?}",
-                dir.as_ref().as_os_str()
-            )
-        })?;
-    file.write_all(cargo.to_string().as_bytes())
-        .and_then(|()| file.write_all(b"\n"))
-        .with_context(|| {
-            format!(
-                "Failed to append to Cargo.toml in {:?}",
-                dir.as_ref().as_os_str()
-            )
-        })
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use std::iter::once;
+
+    use toml_edit::Table;
+
+    use super::{manifest, write_package};
+
+    #[test]
+    fn writes_library_package() {
+        let dependencies = Table::from_iter(once(("itoa", "=1.0.18")));
+        let dir = temp_dir::TempDir::new().expect("temp dir should be created");
+
+        write_package(dir.path(), &manifest("batch1", dependencies))
+            .expect("package should be written");
+
+        assert_eq!(
+            std::fs::read_to_string(dir.path().join("Cargo.toml"))
+                .expect("manifest should be written"),
+            indoc::indoc! {r#"
+                [package]
+                name = "batch1"
+                version = "0.1.0"
+                edition = "2021"
+
+                [dependencies]
+                itoa = "=1.0.18"
+            "#}
+        );
+        assert!(dir.path().join("src/lib.rs").is_file());
+    }
 }
@@ -0,0 +1,165 @@
+//! Offline check whether packages are available locally, as `cargo build --frozen` needs them.
+
+use std::{path::Path, str::FromStr as _};
+
+use cargo_lock::{Package, package::SourceKind};
+use toml_edit::DocumentMut;
+
+use crate::cargo_home;
+
+/// Check if `package` is available without network access.
+///
+/// With `vendor_dir`, the package must be vendored there. Otherwise, registry packages must be in
+/// the registry cache, together with their index entries, git packages in the repository database
+/// with their precise revision, and packages of local registries and directories in those sources.
+pub fn is_available(cargo_home: &Path, package: &Package, vendor_dir: Option<&Path>) -> bool {
+    if let Some(vendor_dir) = vendor_dir {
+        return is_vendored(vendor_dir, package);
+    }
+    let Some(source) = &package.source else {
+        return false;
+    };
+    match source.kind() {
+        SourceKind::Registry | SourceKind::SparseRegistry => {
+            let Some(dir_name) = cargo_home::registry_dir_name(source) else {
+                return false;
+            };
+            let index_dir = cargo_home.join("registry/index").join(dir_name);
+            cargo_home::is_cached(cargo_home, package)
+                && cargo_home::index_cache_path(&index_dir, package.name.as_str()).is_file()
+        }
+        SourceKind::LocalRegistry | SourceKind::Directory => {
+            let Ok(path) = source.url().to_file_path() else {
+                return false;
+            };
+            if *source.kind() == SourceKind::LocalRegistry {
+                path.join(format!("{}-{}.crate", package.name, package.version))
+                    .is_file()
+            } else {
+                is_vendored(&path, package)
+            }
+        }
+        _ => cargo_home::is_cached(cargo_home, package),
+    }
+}
+
+/// Check if `package` is in `dir`, in a directory named like `cargo vendor` names it.
+fn is_vendored(dir: &Path, package: &Package) -> bool {
+    [
+        format!("{}-{}", package.name, package.version),
+        package.name.to_string(),
+    ]
+    .iter()
+    .map(|name| dir.join(name))
+    .filter(|dir| dir.join(".cargo-checksum.json").is_file())
+    .any(|dir| {
+        std::fs::read_to_string(dir.join("Cargo.toml"))
+            .ok()
+            .and_then(|manifest| DocumentMut::from_str(&manifest).ok())
+            .is_some_and(|manifest| {
+                manifest
+                    .get("package")
+                    .and_then(|p| p.get("version"))
+                    .and_then(|v| v.as_str())
+                    == Some(package.version.to_string().as_str())
+            })
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use std::str::FromStr as _;
+
+    use cargo_lock::Lockfile;
+    use itertools::Itertools as _;
+
+    use super::is_available;
+    use crate::cargo_home::git_dir_name;
+
+    #[test]
+    fn finds_vendored_packages_of_the_right_version() {
+        let lockfile = Lockfile::from_str(indoc::indoc! {r#"
+            [[package]]
+            name = "itoa"
+            version = "1.0.18"
+            source = "registry+https://github.com/rust-lang/crates.io-index"
+
+            [[package]]
+            name = "memchr"
+            version = "2.8.0"
+            source = "registry+https://github.com/rust-lang/crates.io-index"
+
+            [[package]]
+            name = "syn"
+            version = "1.0.109"
+            source = "registry+https://github.com/rust-lang/crates.io-index"
+
+            [[package]]
+            name = "syn"
+            version = "2.0.118"
+            source = "registry+https://github.com/rust-lang/crates.io-index"
+        "#})
+        .expect("fixture should parse");
+        let vendor = temp_dir::TempDir::new().expect("temp dir should be created");
+        for (dir, version) in [
+            ("itoa", "1.0.18"),
+            ("memchr", "2.8.3"),
+            ("syn-1.0.109", "1.0.109"),
+        ] {
+            let dir = vendor.path().join(dir);
+            std::fs::create_dir_all(&dir).expect("dir should be created");
+            std::fs::write(dir.join(".cargo-checksum.json"), "{}").expect("checksums written");
+            std::fs::write(
+                dir.join("Cargo.toml"),
+                format!("[package]\nversion = \"{version}\"\n"),
+            )
+            .expect("manifest should be written");
+        }
+        let home = temp_dir::TempDir::new().expect("temp dir should be created");
+
+        let available = lockfile
+            .packages
+            .iter()
+            .map(|p| is_available(home.path(), p, Some(vendor.path())))
+            .collect_vec();
+
+        assert_eq!(available, vec![true, false, true, false]);
+    }
+
+    #[test]
+    fn finds_git_packages_by_revision_in_the_database() {
+        let lockfile = Lockfile::from_str(indoc::indoc! {r#"
+            [[package]]
+            name = "tool"
+            version = "0.1.0"
+            source = "git+https://git.example.com/tool#0123456789abcdef0123456789abcdef01234567"
+
+            [[package]]
+            name = "tool"
+            version = "0.2.0"
+            source = "git+https://git.example.com/tool#89abcdef0123456789abcdef0123456789abcdef"
+        "#})
+        .expect("fixture should parse");
+        let home = temp_dir::TempDir::new().expect("temp dir should be created");
+        let source = lockfile.packages[0]
+            .source
+            .as_ref()
+            .expect("fixture has source");
+        let db = home
+            .path()
+            .join("git/db")
+            .join(git_dir_name(source).expect("source is git"));
+        let objects = db.join("objects/01");
+        std::fs::create_dir_all(&objects).expect("objects dir should be created");
+        std::fs::write(objects.join("23456789abcdef0123456789abcdef01234567"), "")
+            .expect("object should be written");
+
+        let available = lockfile
+            .packages
+            .iter()
+            .map(|p| is_available(home.path(), p, None))
+            .collect_vec();
+
+        assert_eq!(available, vec![true, false]);
+    }
+}
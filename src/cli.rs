@@ -2,6 +2,8 @@ use clap::error::ErrorKind;
 use clap_cargo::style::CLAP_STYLING;
 use indoc::indoc;
 
+use crate::package_filter::PackageFilter;
+
 #[derive(clap::Parser, Debug)]
 #[command(
     name = "cargo",
@@ -36,9 +38,20 @@ pub struct CargoLockFetchCli {
         long,
         value_name = "PATH",
         default_value = "Cargo.lock",
-        help = "Path to Cargo.lock"
+        help = "Path to Cargo.lock, may be repeated to fetch the union of many lockfiles"
     )]
-    pub lockfile_path: String,
+    pub lockfile_path: Vec<String>,
+
+    #[arg(
+        long,
+        value_name = "DIR",
+        conflicts_with = "lockfile_path",
+        help = indoc! {"
+            Fetch all Cargo.lock files found in <DIR>, recursively, skipping target directories
+            and paths excluded by .gitignore
+        "}
+    )]
+    pub discover: Option<String>,
 
     #[arg(
         name = "vendor",
@@ -49,6 +62,205 @@ pub struct CargoLockFetchCli {
     )]
     pub vendor_dir: Option<String>,
 
+    #[arg(
+        long,
+        default_value = "false",
+        requires = "vendor",
+        help = "Always include version in subdir name when vendoring, like cargo vendor --versioned-dirs"
+    )]
+    pub versioned_dirs: bool,
+
+    #[arg(
+        long,
+        default_value = "false",
+        help = "Also fetch crates of [[patch.unused]] entries, which other builds may activate"
+    )]
+    pub unused_patches: bool,
+
+    #[arg(
+        long,
+        short,
+        value_name = "MEMBER",
+        help = indoc! {"
+            Only fetch dependencies of workspace member <MEMBER>, may be repeated to fetch
+            dependencies of many members
+        "}
+    )]
+    pub package: Vec<String>,
+
+    #[arg(
+        long,
+        value_name = "OLD_LOCKFILE",
+        help = indoc! {"
+            Only fetch crates which are not in <OLD_LOCKFILE>, e.g. to add a thin layer on top of
+            dependencies fetched from an older version of the lockfile
+        "}
+    )]
+    pub since: Option<String>,
+
+    #[arg(
+        long,
+        value_name = "DIR",
+        help = indoc! {"
+            Look for workspace members and path dependencies, which are never fetched, in <DIR>
+            and print where they are
+        "}
+    )]
+    pub workspace_root: Option<String>,
+
+    #[arg(
+        long,
+        value_name = "FILTER",
+        help = indoc! {"
+            Only fetch crates matching <FILTER>, may be repeated; <FILTER> is a crate name glob,
+            optionally followed by @<VERSION>, source=<registry|sparse|git>,
+            registry=<INDEX_URL> or git=<REPOSITORY_URL>
+        "}
+    )]
+    pub include: Vec<PackageFilter>,
+
+    #[arg(
+        long,
+        value_name = "FILTER",
+        help = "Do not fetch crates matching <FILTER>, may be repeated, see --include for syntax"
+    )]
+    pub exclude: Vec<PackageFilter>,
+
+    #[arg(
+        long,
+        default_value = "false",
+        help = indoc! {"
+            Download crates from crates.io and other sparse registries directly, in parallel,
+            instead of through cargo fetch, which is then only run for the remaining crates, and
+            vendor them without cargo vendor if there are no other crates; source replacement
+            configured for cargo is not applied to these downloads
+        "}
+    )]
+    pub native: bool,
+
+    #[arg(
+        long,
+        default_value = "false",
+        help = indoc! {"
+            Generate a single manifest, depending on many versions of a crate through renamed
+            dependencies, instead of distributing them between sub-crates
+        "}
+    )]
+    pub single_manifest: bool,
+
+    #[arg(
+        long,
+        default_value = "false",
+        help = indoc! {"
+            Do not run cargo, print the generated manifests, .cargo/config.toml and Cargo.lock
+            files to stdout instead
+        "}
+    )]
+    pub dry_run: bool,
+
+    #[arg(
+        long,
+        default_value = "false",
+        conflicts_with = "dry_run",
+        help = indoc! {"
+            Do not fetch anything, only check without network access that all crates are
+            available locally, in cargo's cache or the --vendor directory, print the missing
+            ones and fail if there are any
+        "}
+    )]
+    pub check: bool,
+
+    #[arg(
+        long,
+        default_value = "false",
+        help = indoc! {"
+            Fail if any crate to fetch is not reproducible: git branches without a precise
+            revision, registry crates without a checksum and path sources
+        "}
+    )]
+    pub strict: bool,
+
+    #[arg(
+        long,
+        default_value = "false",
+        help = indoc! {"
+            After fetching, check the checksums of all fetched registry crates in cargo's cache
+            against the lockfile
+        "}
+    )]
+    pub verify: bool,
+
+    #[arg(
+        long,
+        default_value = "false",
+        help = "Like --verify, but remove corrupted crates from the cache and fetch them, and missing ones, again"
+    )]
+    pub repair: bool,
+
+    #[arg(
+        long,
+        default_value = "false",
+        help = indoc! {"
+            When cargo fails, leave out the crates it failed on, and the crates depending on them,
+            and run it again, to fetch everything that is still available; exit with code 3 if any
+            crates were left out
+        "}
+    )]
+    pub keep_going: bool,
+
+    #[arg(
+        long,
+        value_name = "N",
+        default_value = "0",
+        help = indoc! {"
+            Run cargo again, up to <N> times, when it fails with a network error that may not
+            happen again, or times out
+        "}
+    )]
+    pub retries: u32,
+
+    #[arg(
+        long,
+        value_name = "SECONDS",
+        default_value = "1",
+        requires = "retries",
+        help = indoc! {"
+            Wait <SECONDS> before running cargo again, twice as long before every next retry, up
+            to 10 minutes
+        "}
+    )]
+    pub retry_backoff: u64,
+
+    #[arg(
+        long,
+        value_name = "SECONDS",
+        help = indoc! {"
+            Kill cargo, and the processes it started, if it runs longer than <SECONDS>, which
+            fails, or retries, the run
+        "}
+    )]
+    pub timeout: Option<u64>,
+
+    #[arg(
+        long,
+        value_name = "FILE",
+        help = indoc! {"
+            Write a JSON report of all fetched crates, with their batches, registries and whether
+            they were cached already, and of cargo's exit status to <FILE>
+        "}
+    )]
+    pub report: Option<String>,
+
+    #[arg(
+        long,
+        value_name = "FILE",
+        help = indoc! {"
+            After a successful run, write the SHA-256 digest of the input lockfiles, as read
+            before fetching, to <FILE>, e.g. to tell whether a cache layer is up to date
+        "}
+    )]
+    pub stamp: Option<String>,
+
     #[arg(
         long,
         short,
@@ -0,0 +1,187 @@
+//! Dependency graph recorded in a lockfile.
+
+use std::collections::{HashMap, HashSet};
+
+use cargo_lock::{Dependency, Name, Package, Version};
+use itertools::Itertools as _;
+
+/// Identity of a package or dependency, insensitive to how git sources are spelled in each.
+pub type Identity = (Name, Version, Option<String>);
+
+pub fn identity(d: &Dependency) -> Identity {
+    let source = d.source.as_ref().map(|s| s.url().to_string());
+    (d.name.clone(), d.version.clone(), source)
+}
+
+/// Keep only the packages reachable from workspace members named `members`.
+///
+/// Workspace members are the packages without a source. Both dependencies and `[replace]`
+/// references are followed.
+pub fn members_closure(packages: Vec<Package>, members: &[String]) -> Vec<Package> {
+    let by_identity: HashMap<_, _> = packages
+        .iter()
+        .enumerate()
+        .map(|(i, p)| (identity(&Dependency::from(p)), i))
+        .collect();
+
+    let mut reachable = HashSet::new();
+    let mut queue = packages
+        .iter()
+        .positions(|p| p.source.is_none() && members.iter().any(|m| m == p.name.as_str()))
+        .collect_vec();
+    while let Some(i) = queue.pop() {
+        if !reachable.insert(i) {
+            continue;
+        }
+        let p = &packages[i];
+        queue.extend(
+            p.dependencies
+                .iter()
+                .chain(&p.replace)
+                .filter_map(|d| by_identity.get(&identity(d)).copied()),
+        );
+    }
+
+    packages
+        .into_iter()
+        .enumerate()
+        .filter(|(i, _)| reachable.contains(i))
+        .map(|(_, p)| p)
+        .collect()
+}
+
+/// Identities of `roots` and of all `packages` which depend on any of them, transitively.
+pub fn dependents_closure(packages: &[Package], roots: HashSet<Identity>) -> HashSet<Identity> {
+    let mut closure = roots;
+    loop {
+        let dependents = packages
+            .iter()
+            .map(|p| identity(&Dependency::from(p)))
+            .zip(packages)
+            .filter(|(i, p)| {
+                !closure.contains(i)
+                    && p.dependencies
+                        .iter()
+                        .any(|d| closure.contains(&identity(d)))
+            })
+            .map(|(i, _)| i)
+            .collect_vec();
+        if dependents.is_empty() {
+            return closure;
+        }
+        closure.extend(dependents);
+    }
+}
+
+/// Names of workspace members, i.e. packages without a source.
+pub fn members(packages: &[Package]) -> impl Iterator<Item = &str> {
+    packages
+        .iter()
+        .filter(|p| p.source.is_none())
+        .map(|p| p.name.as_str())
+}
+
+#[cfg(test)]
+mod test {
+    use std::str::FromStr as _;
+
+    use cargo_lock::Lockfile;
+    use itertools::Itertools as _;
+
+    use super::{dependents_closure, identity, members_closure};
+
+    const LOCKFILE: &str = r#"
+version = 4
+
+[[package]]
+name = "app"
+version = "0.1.0"
+dependencies = [
+ "common",
+ "syn 2.0.118",
+]
+
+[[package]]
+name = "common"
+version = "0.1.0"
+dependencies = [
+ "memchr",
+]
+
+[[package]]
+name = "memchr"
+version = "2.8.2"
+source = "registry+https://github.com/rust-lang/crates.io-index"
+checksum = "88904434abc2901f197fe8cc55f0445e7ded921dba5911dad2e2b39b48e663c4"
+
+[[package]]
+name = "syn"
+version = "1.0.109"
+source = "registry+https://github.com/rust-lang/crates.io-index"
+checksum = "deadbeefdeadbeefdeadbeefdeadbeefdeadbeefdeadbeefdeadbeefdeadbeef"
+
+[[package]]
+name = "syn"
+version = "2.0.118"
+source = "registry+https://github.com/rust-lang/crates.io-index"
+checksum = "cafebabecafebabecafebabecafebabecafebabecafebabecafebabecafebabe"
+
+[[package]]
+name = "tool"
+version = "0.1.0"
+dependencies = [
+ "syn 1.0.109",
+]
+"#;
+
+    fn closure(members: &[&str]) -> Vec<String> {
+        let lockfile = Lockfile::from_str(LOCKFILE).expect("fixture should parse");
+        let members = members.iter().map(|m| m.to_string()).collect_vec();
+        members_closure(lockfile.packages, &members)
+            .iter()
+            .map(|p| format!("{}@{}", p.name, p.version))
+            .collect_vec()
+    }
+
+    #[test]
+    fn follows_dependencies_transitively() {
+        assert_eq!(
+            closure(&["app"]),
+            vec!["app@0.1.0", "common@0.1.0", "memchr@2.8.2", "syn@2.0.118"]
+        );
+    }
+
+    #[test]
+    fn unions_closures_of_many_members() {
+        assert_eq!(
+            closure(&["common", "tool"]),
+            vec!["common@0.1.0", "memchr@2.8.2", "syn@1.0.109", "tool@0.1.0"]
+        );
+    }
+
+    #[test]
+    fn finds_dependents_transitively() {
+        let lockfile = Lockfile::from_str(LOCKFILE).expect("fixture should parse");
+        let memchr = lockfile
+            .packages
+            .iter()
+            .find(|p| p.name.as_str() == "memchr")
+            .expect("fixture has memchr");
+
+        let closure = dependents_closure(&lockfile.packages, [identity(&memchr.into())].into());
+
+        assert_eq!(
+            closure
+                .iter()
+                .map(|(name, version, _)| format!("{name}@{version}"))
+                .sorted()
+                .collect_vec(),
+            vec!["app@0.1.0", "common@0.1.0", "memchr@2.8.2"]
+        );
+    }
+
+    #[test]
+    fn only_source_less_packages_are_members() {
+        assert!(closure(&["memchr"]).is_empty());
+    }
+}
@@ -0,0 +1,505 @@
+//! Native download of registry crates, without cargo.
+//!
+//! Crates from sparse registries, including crates.io, which have a checksum in the lockfile are
+//! downloaded directly, in parallel. Their `.crate` files and index entries are stored in cargo's
+//! home the same way cargo stores them, so that cargo finds them later, even offline.
+
+use std::{
+    path::{Path, PathBuf},
+    sync::{
+        Mutex,
+        atomic::{AtomicUsize, Ordering},
+    },
+};
+
+use anyhow::Context as _;
+use cargo_lock::{Checksum, Package, SourceId, package::SourceKind};
+use itertools::Itertools as _;
+use log::debug;
+use sha2::{Digest as _, Sha256};
+
+use crate::cargo_home;
+
+/// Number of crates downloaded at the same time.
+const PARALLEL_DOWNLOADS: usize = 16;
+
+/// Cargo's sparse index of crates.io, which it reaches the git index through by default.
+const CRATES_IO_INDEX: &str = "https://index.crates.io/";
+
+/// Version of cargo's index cache file format, followed by the version of the index format.
+const INDEX_CACHE_HEADER: [u8; 5] = [3, 2, 0, 0, 0];
+
+#[derive(thiserror::Error, Debug)]
+pub enum DownloadError {
+    #[error("request to {url} failed: {source}")]
+    Http {
+        url: String,
+        #[source]
+        source: Box<ureq::Error>,
+    },
+    #[error("invalid registry configuration at {url}: {reason}")]
+    Config { url: String, reason: String },
+    #[error("checksum mismatch, expected {expected}, found {actual}")]
+    Checksum {
+        expected: Checksum,
+        actual: Checksum,
+    },
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
+
+/// URL of the sparse index of registry `source`, `None` if it is not a sparse registry.
+fn index_url(source: &SourceId) -> Option<String> {
+    match source.kind() {
+        SourceKind::Registry | SourceKind::SparseRegistry if source.is_default_registry() => {
+            Some(CRATES_IO_INDEX.to_string())
+        }
+        SourceKind::SparseRegistry => {
+            let url = source.url().as_str();
+            Some(if url.ends_with('/') {
+                url.to_string()
+            } else {
+                format!("{url}/")
+            })
+        }
+        _ => None,
+    }
+}
+
+/// Check if `package` can be downloaded without cargo.
+pub fn is_downloadable(package: &Package) -> bool {
+    package.checksum.is_some() && package.source.as_ref().and_then(index_url).is_some()
+}
+
+/// Outcome of downloading crates.
+#[derive(Debug)]
+pub struct Downloads<'a> {
+    /// Number of downloaded crates.
+    pub downloaded: usize,
+    /// Packages that could not be downloaded, and why.
+    ///
+    /// A package fails with its `.crate` file, but also with its index entries or the configuration
+    /// of its registry.
+    pub failed: Vec<(&'a Package, String)>,
+    /// Packages of registries that require authentication, left for cargo to download with its
+    /// credentials.
+    pub left_to_cargo: Vec<&'a Package>,
+}
+
+/// Download all downloadable `packages` which are not cached in `cargo_home` yet.
+///
+/// Failing downloads do not stop the others. Only failures to store downloads in `cargo_home` are
+/// errors.
+pub fn download<'a>(
+    cargo_home: &Path,
+    packages: impl IntoIterator<Item = &'a Package>,
+) -> Result<Downloads<'a>, anyhow::Error> {
+    let agent = ureq::Agent::new_with_defaults();
+    let registries = packages
+        .into_iter()
+        .filter(|p| is_downloadable(p) && !cargo_home::is_cached(cargo_home, p))
+        .unique_by(|p| (&p.name, &p.version, &p.source))
+        .into_group_map_by(|p| {
+            let source = p
+                .source
+                .as_ref()
+                .expect("downloadable package has a source");
+            index_url(source).expect("downloadable package is from a sparse registry")
+        });
+    let mut tasks = vec![];
+    let mut failed = vec![];
+    let mut left_to_cargo = vec![];
+    for (index, packages) in &registries {
+        let source = packages[0].source.as_ref().expect("package has a source");
+        let dir_name = cargo_home::registry_dir_name(source).expect("registry has a directory");
+        let index_dir = cargo_home.join("registry/index").join(&dir_name);
+        let dl = match registry_config(&agent, index, &index_dir) {
+            Ok(Some(dl)) => dl,
+            Ok(None) => {
+                debug!(index; "registry requires authentication, leaving it to cargo");
+                left_to_cargo.extend(packages.iter().copied());
+                continue;
+            }
+            Err(error) => {
+                let reason = format!("failed to configure registry {index}: {error}");
+                failed.extend(packages.iter().map(|p| (*p, reason.clone())));
+                continue;
+            }
+        };
+        let cache_dir = cargo_home.join("registry/cache").join(&dir_name);
+        std::fs::create_dir_all(&cache_dir)
+            .with_context(|| format!("failed to create {}", cache_dir.display()))?;
+        tasks.extend(
+            packages
+                .iter()
+                .copied()
+                .into_group_map_by(|p| p.name.as_str().to_lowercase())
+                .into_iter()
+                .map(|(name, packages)| Task::Index {
+                    url: format!("{index}{}/{name}", cargo_home::index_prefix(&name)),
+                    path: cargo_home::index_cache_path(&index_dir, &name),
+                    packages,
+                }),
+        );
+        tasks.extend(packages.iter().map(|p| Task::Crate {
+            package: p,
+            url: crate_url(&dl, p),
+            path: cache_dir.join(format!("{}-{}.crate", p.name, p.version)),
+        }));
+    }
+
+    let crates = tasks
+        .iter()
+        .filter(|t| matches!(t, Task::Crate { .. }))
+        .count();
+    let failed_crates = AtomicUsize::new(0);
+    let tasks = Mutex::new(tasks.into_iter());
+    let failed = Mutex::new(failed);
+    std::thread::scope(|scope| {
+        for _ in 0..PARALLEL_DOWNLOADS {
+            scope.spawn(|| {
+                loop {
+                    let Some(task) = tasks.lock().expect("no download thread panics").next() else {
+                        break;
+                    };
+                    if let Err(error) = task.run(&agent) {
+                        let mut failed = failed.lock().expect("no download thread panics");
+                        match &task {
+                            Task::Index { url, packages, .. } => {
+                                let reason = format!("failed to download index {url}: {error}");
+                                failed.extend(packages.iter().map(|p| (*p, reason.clone())));
+                            }
+                            Task::Crate { package, .. } => {
+                                failed_crates.fetch_add(1, Ordering::Relaxed);
+                                failed.push((package, format!("failed to download: {error}")));
+                            }
+                        }
+                    }
+                }
+            });
+        }
+    });
+    let failed = failed
+        .into_inner()
+        .expect("no download thread panics")
+        .into_iter()
+        .sorted_by_key(|(p, _)| (&p.name, &p.version))
+        .unique_by(|(p, _)| (&p.name, &p.version, &p.source))
+        .collect();
+    Ok(Downloads {
+        downloaded: crates - failed_crates.into_inner(),
+        failed,
+        left_to_cargo,
+    })
+}
+
+/// A file to download to cargo's home.
+enum Task<'a> {
+    /// Index entries of a crate, stored in cargo's index cache format.
+    Index {
+        url: String,
+        path: PathBuf,
+        /// Packages of the crate to download, which cargo cannot use without the entries.
+        packages: Vec<&'a Package>,
+    },
+    /// A `.crate` file, verified against the lockfile before it is stored.
+    Crate {
+        package: &'a Package,
+        url: String,
+        path: PathBuf,
+    },
+}
+
+impl Task<'_> {
+    fn run(&self, agent: &ureq::Agent) -> Result<(), DownloadError> {
+        match self {
+            Task::Index { url, path, .. } => {
+                let (body, index_version) = get(agent, url)?;
+                let entries = String::from_utf8_lossy(&body);
+                write_atomically(path, &index_cache(&entries, index_version.as_deref()))
+            }
+            Task::Crate { package, url, path } => {
+                let (body, _) = get(agent, url)?;
+                let expected = package.checksum.clone().expect("crate has a checksum");
+                let actual = Checksum::from(<[u8; 32]>::from(Sha256::digest(&body)));
+                if actual != expected {
+                    return Err(DownloadError::Checksum { expected, actual });
+                }
+                write_atomically(path, &body)
+            }
+        }
+    }
+}
+
+/// Download the `dl` URL template from the `config.json` of sparse registry `index`.
+///
+/// The configuration is also written to `index_dir`, where cargo keeps it. `None` means that the
+/// registry requires authentication, which only cargo has the credentials for.
+fn registry_config(
+    agent: &ureq::Agent,
+    index: &str,
+    index_dir: &Path,
+) -> Result<Option<String>, DownloadError> {
+    let url = format!("{index}config.json");
+    let body = match get(agent, &url) {
+        Ok((body, _)) => body,
+        Err(DownloadError::Http { source, .. })
+            if matches!(*source, ureq::Error::StatusCode(401 | 403)) =>
+        {
+            return Ok(None);
+        }
+        Err(error) => return Err(error),
+    };
+    let config: serde_json::Value =
+        serde_json::from_slice(&body).map_err(|e| DownloadError::Config {
+            url: url.clone(),
+            reason: e.to_string(),
+        })?;
+    if config.get("auth-required").and_then(|a| a.as_bool()) == Some(true) {
+        return Ok(None);
+    }
+    let dl = config
+        .get("dl")
+        .and_then(|dl| dl.as_str())
+        .ok_or_else(|| DownloadError::Config {
+            url: url.clone(),
+            reason: "missing dl".to_string(),
+        })?;
+    write_atomically(&index_dir.join("config.json"), &body)?;
+    Ok(Some(dl.to_string()))
+}
+
+/// Download `url`, returning its contents and their version, as cargo records it in index caches.
+fn get(agent: &ureq::Agent, url: &str) -> Result<(Vec<u8>, Option<String>), DownloadError> {
+    debug!(url; "downloading");
+    let http = |source| DownloadError::Http {
+        url: url.to_string(),
+        source: Box::new(source),
+    };
+    let mut response = agent.get(url).call().map_err(http)?;
+    let version = ["etag", "last-modified"].into_iter().find_map(|header| {
+        let value = response.headers().get(header)?.to_str().ok()?;
+        Some(format!("{header}: {value}"))
+    });
+    let body = response
+        .body_mut()
+        .with_config()
+        .limit(u64::MAX)
+        .read_to_vec()
+        .map_err(http)?;
+    Ok((body, version))
+}
+
+/// Write `contents` to `path`, so that no partially written file is ever seen there.
+fn write_atomically(path: &Path, contents: &[u8]) -> Result<(), DownloadError> {
+    let dir = path.parent().expect("file is in a directory");
+    std::fs::create_dir_all(dir)?;
+    let tmp = dir.join(format!(
+        ".{}.part",
+        path.file_name().expect("file has a name").to_string_lossy()
+    ));
+    std::fs::write(&tmp, contents)?;
+    std::fs::rename(&tmp, path)?;
+    Ok(())
+}
+
+/// Download URL of `package`, following cargo's rules for the `dl` template.
+fn crate_url(dl: &str, package: &Package) -> String {
+    const MARKERS: [&str; 5] = [
+        "{crate}",
+        "{version}",
+        "{prefix}",
+        "{lowerprefix}",
+        "{sha256-checksum}",
+    ];
+    let name = package.name.as_str();
+    let template = if MARKERS.iter().any(|m| dl.contains(m)) {
+        dl.to_string()
+    } else {
+        format!(
+            "{}/{{crate}}/{{version}}/download",
+            dl.trim_end_matches('/')
+        )
+    };
+    template
+        .replace("{crate}", name)
+        .replace("{version}", &package.version.to_string())
+        .replace("{prefix}", &cargo_home::index_prefix(name))
+        .replace(
+            "{lowerprefix}",
+            &cargo_home::index_prefix(&name.to_lowercase()),
+        )
+        .replace(
+            "{sha256-checksum}",
+            &package
+                .checksum
+                .as_ref()
+                .map(ToString::to_string)
+                .unwrap_or_default(),
+        )
+}
+
+/// Contents of cargo's index cache file for a crate with index `entries`.
+///
+/// Cargo revalidates the cache with the index server using the recorded `index_version`, if any.
+fn index_cache(entries: &str, index_version: Option<&str>) -> Vec<u8> {
+    let mut cache = INDEX_CACHE_HEADER.to_vec();
+    cache.extend(index_version.unwrap_or("Unknown").as_bytes());
+    cache.push(0);
+    for entry in entries.lines().filter(|l| !l.trim().is_empty()) {
+        let Some(version) = serde_json::from_str::<serde_json::Value>(entry)
+            .ok()
+            .and_then(|e| e.get("vers")?.as_str().map(str::to_owned))
+        else {
+            continue;
+        };
+        cache.extend(version.as_bytes());
+        cache.push(0);
+        cache.extend(entry.as_bytes());
+        cache.push(0);
+    }
+    cache
+}
+
+#[cfg(test)]
+mod test {
+    use std::str::FromStr as _;
+
+    use cargo_lock::Lockfile;
+    use itertools::Itertools as _;
+
+    use super::{crate_url, download, index_cache};
+
+    const ENTRY: &str = r#"{"name":"hello","vers":"1.0.0","deps":[],"cksum":"2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824","features":{},"yanked":false}"#;
+
+    fn lockfile(index: &str) -> Lockfile {
+        Lockfile::from_str(&format!(
+            r#"
+[[package]]
+name = "hello"
+version = "1.0.0"
+source = "sparse+{index}"
+checksum = "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824"
+
+[[package]]
+name = "bad"
+version = "1.0.0"
+source = "sparse+{index}"
+checksum = "0000000000000000000000000000000000000000000000000000000000000000"
+"#
+        ))
+        .expect("fixture should parse")
+    }
+
+    #[test]
+    fn expands_dl_templates() {
+        let lockfile = lockfile("https://my.registry/index/");
+        let package = &lockfile.packages[0];
+
+        assert_eq!(
+            crate_url("https://dl.my.registry/api/v1/crates", package),
+            "https://dl.my.registry/api/v1/crates/hello/1.0.0/download"
+        );
+        assert_eq!(
+            crate_url(
+                "https://dl.my.registry/{lowerprefix}/{crate}-{version}",
+                package
+            ),
+            "https://dl.my.registry/he/ll/hello-1.0.0"
+        );
+    }
+
+    #[test]
+    fn downloads_verified_crates_from_sparse_registry() {
+        let server = tiny_http::Server::http("127.0.0.1:0").expect("server should start");
+        let index = format!("http://{}/index/", server.server_addr());
+        let config = format!(r#"{{"dl":"http://{}/dl"}}"#, server.server_addr());
+        std::thread::spawn(move || {
+            for request in server.incoming_requests() {
+                let body = match request.url() {
+                    "/index/config.json" => config.clone(),
+                    "/index/he/ll/hello" | "/index/3/b/bad" => format!("{ENTRY}\n"),
+                    "/dl/hello/1.0.0/download" | "/dl/bad/1.0.0/download" => "hello".to_string(),
+                    _ => {
+                        let _ = request.respond(tiny_http::Response::empty(404));
+                        continue;
+                    }
+                };
+                let _ = request.respond(tiny_http::Response::from_string(body));
+            }
+        });
+        let home = temp_dir::TempDir::new().expect("temp dir should be created");
+
+        let lockfile = lockfile(&index);
+        let downloads = download(home.path(), &lockfile.packages).expect("cache is writable");
+
+        assert_eq!(downloads.downloaded, 1);
+        assert_eq!(
+            downloads
+                .failed
+                .iter()
+                .map(|(p, reason)| (
+                    p.name.as_str(),
+                    reason.starts_with("failed to download: checksum mismatch")
+                ))
+                .collect_vec(),
+            [("bad", true)]
+        );
+        let registry_dir = std::fs::read_dir(home.path().join("registry/cache"))
+            .expect("cache should be created")
+            .next()
+            .expect("registry dir should be created")
+            .expect("registry dir should be listed")
+            .file_name();
+        let cache = home.path().join("registry/cache").join(&registry_dir);
+        assert_eq!(
+            std::fs::read(cache.join("hello-1.0.0.crate")).expect("crate should be stored"),
+            b"hello"
+        );
+        assert!(!cache.join("bad-1.0.0.crate").exists());
+        let index_dir = home.path().join("registry/index").join(&registry_dir);
+        assert_eq!(
+            std::fs::read(index_dir.join(".cache/he/ll/hello")).expect("index should be cached"),
+            index_cache(ENTRY, None)
+        );
+        assert!(index_dir.join("config.json").exists());
+    }
+
+    #[test]
+    fn leaves_registries_requiring_authentication_to_cargo() {
+        for config in [
+            None,
+            Some(r#"{"dl":"http://127.0.0.1/dl","auth-required":true}"#),
+        ] {
+            let server = tiny_http::Server::http("127.0.0.1:0").expect("server should start");
+            let index = format!("http://{}/index/", server.server_addr());
+            std::thread::spawn(move || {
+                for request in server.incoming_requests() {
+                    let response = match (request.url(), config) {
+                        ("/index/config.json", Some(config)) => {
+                            tiny_http::Response::from_string(config)
+                        }
+                        _ => tiny_http::Response::from_string("").with_status_code(401),
+                    };
+                    let _ = request.respond(response);
+                }
+            });
+            let home = temp_dir::TempDir::new().expect("temp dir should be created");
+            let lockfile = lockfile(&index);
+
+            let downloads = download(home.path(), &lockfile.packages).expect("cache is writable");
+
+            assert_eq!(downloads.downloaded, 0);
+            assert!(downloads.failed.is_empty());
+            assert_eq!(
+                downloads
+                    .left_to_cargo
+                    .iter()
+                    .map(|p| p.name.as_str())
+                    .sorted()
+                    .collect_vec(),
+                ["bad", "hello"]
+            );
+        }
+    }
+}
@@ -0,0 +1,166 @@
+//! Local packages: workspace members and path dependencies.
+//!
+//! Cargo.lock records neither the location nor the manifest of local packages, which are usually
+//! source-less, so they cannot be added to the generated project. They need not be fetched either,
+//! and all their registry and git dependencies are packages of the lockfile on their own.
+
+use std::{
+    path::{Path, PathBuf},
+    str::FromStr as _,
+};
+
+use cargo_lock::{Name, Package, Version, package::SourceKind};
+use itertools::Itertools as _;
+use log::warn;
+use toml_edit::DocumentMut;
+
+use crate::lockfiles;
+
+/// A local package, located in the workspace if possible.
+#[derive(serde::Serialize, Debug, PartialEq, Eq)]
+pub struct LocalPackage {
+    pub name: Name,
+    pub version: Version,
+    /// Directory of the package, `None` if it could not be found.
+    pub path: Option<PathBuf>,
+}
+
+pub fn is_local(package: &Package) -> bool {
+    package
+        .source
+        .as_ref()
+        .is_none_or(|s| *s.kind() == SourceKind::Path)
+}
+
+/// Locate local `packages`.
+///
+/// Packages with a `path+` source are where the source says. Source-less packages are looked for
+/// among the manifests found under `workspace_root`, if given.
+pub fn locate<'a>(
+    packages: impl IntoIterator<Item = &'a Package>,
+    workspace_root: Option<&Path>,
+) -> Result<Vec<LocalPackage>, anyhow::Error> {
+    let manifests = workspace_root
+        .map(manifests)
+        .transpose()?
+        .unwrap_or_default();
+    Ok(packages
+        .into_iter()
+        .map(|p| {
+            let path = match &p.source {
+                Some(source) => source.url().to_file_path().ok(),
+                None => manifests
+                    .iter()
+                    .find(|(name, version, _)| {
+                        name == p.name.as_str() && version.as_ref().is_none_or(|v| *v == p.version)
+                    })
+                    .map(|(_, _, dir)| dir.clone()),
+            };
+            if path.is_none() && workspace_root.is_some() {
+                warn!(name:% = p.name, version:% = p.version; "local crate not found in workspace");
+            }
+            LocalPackage {
+                name: p.name.clone(),
+                version: p.version.clone(),
+                path,
+            }
+        })
+        .collect())
+}
+
+/// Name, version and directory of every package manifest under `dir`.
+///
+/// The version is `None` if it is inherited from the workspace. Manifests are found like lockfiles
+/// are discovered, skipping ignored paths and `target` directories.
+fn manifests(dir: &Path) -> Result<Vec<(String, Option<Version>, PathBuf)>, anyhow::Error> {
+    Ok(lockfiles::find_files(dir, "Cargo.toml")?
+        .into_iter()
+        .filter_map(|path| {
+            let manifest = std::fs::read_to_string(&path)
+                .inspect_err(|error| warn!(error:%, path:?; "could not read manifest"))
+                .ok()?;
+            let manifest = DocumentMut::from_str(&manifest)
+                .inspect_err(|error| warn!(error:%, path:?; "could not parse manifest"))
+                .ok()?;
+            let package = manifest.get("package")?;
+            let name = package.get("name")?.as_str()?.to_string();
+            let version = match package.get("version") {
+                // Since cargo 1.75, a missing version means 0.0.0.
+                None => Some(Version::new(0, 0, 0)),
+                Some(v) => v.as_str().and_then(|v| Version::parse(v).ok()),
+            };
+            let dir = path.parent().expect("manifest should be in a directory");
+            Some((name, version, dir.to_owned()))
+        })
+        .sorted()
+        .collect_vec())
+}
+
+#[cfg(test)]
+mod test {
+    use std::str::FromStr as _;
+
+    use cargo_lock::Lockfile;
+    use itertools::Itertools as _;
+
+    use super::{is_local, locate};
+
+    #[test]
+    fn locates_members_and_path_dependencies_in_workspace() {
+        let root = temp_dir::TempDir::new().expect("temp dir should be created");
+        let manifests = [
+            ("app", "[package]\nname = \"app\"\nversion = \"0.1.0\"\n"),
+            (
+                "utils",
+                "[package]\nname = \"utils\"\nversion.workspace = true\n",
+            ),
+            (
+                "target/stale",
+                "[package]\nname = \"stale\"\nversion = \"0.1.0\"\n",
+            ),
+            (".", "[workspace]\nmembers = [\"app\", \"utils\"]\n"),
+        ];
+        for (dir, manifest) in manifests {
+            let dir = root.path().join(dir);
+            std::fs::create_dir_all(&dir).expect("dir should be created");
+            std::fs::write(dir.join("Cargo.toml"), manifest).expect("manifest should be written");
+        }
+        let lockfile = Lockfile::from_str(indoc::indoc! {r#"
+            [[package]]
+            name = "app"
+            version = "0.1.0"
+
+            [[package]]
+            name = "memchr"
+            version = "2.8.0"
+            source = "registry+https://github.com/rust-lang/crates.io-index"
+
+            [[package]]
+            name = "stale"
+            version = "0.1.0"
+
+            [[package]]
+            name = "utils"
+            version = "0.2.0"
+        "#})
+        .expect("fixture should parse");
+
+        let local = locate(
+            lockfile.packages.iter().filter(|p| is_local(p)),
+            Some(root.path()),
+        )
+        .expect("workspace should be searched");
+
+        assert_eq!(
+            local
+                .iter()
+                .map(|p| (p.name.as_str(), p.path.clone()))
+                .collect_vec(),
+            vec![
+                ("app", Some(root.path().join("app"))),
+                ("stale", None),
+                ("utils", Some(root.path().join("utils"))),
+            ]
+        );
+    }
+}
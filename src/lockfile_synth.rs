@@ -1,9 +1,14 @@
-use std::{iter::once, path::Path, str::FromStr as _};
+use std::{collections::HashSet, iter::once, path::Path, str::FromStr as _};
 
 use anyhow::Context as _;
-use cargo_lock::{Dependency, Lockfile, Name, Package, ResolveVersion, Version};
+use cargo_lock::{
+    Dependency, Lockfile, Metadata, MetadataKey, MetadataValue, Name, Package, ResolveVersion,
+    SourceId, Version, package::SourceKind,
+};
 use itertools::Itertools as _;
 
+use crate::dependency_graph::identity;
+
 /// Build a Cargo.lock for the generated fake project.
 ///
 /// Versions already recorded in a workspace's Cargo.lock are exempt from cargo's yank
@@ -11,10 +16,25 @@ use itertools::Itertools as _;
 /// versions pinned by the original lockfile. The lockfile does not have to match fresh
 /// resolution exactly: cargo may rewrite it around feature-dependent edges, but keeps
 /// the locked versions, which the manifests pin as `=version` anyway.
-pub fn synthesize(version: ResolveVersion, batches: &[(String, Vec<Package>)]) -> Lockfile {
+///
+/// V1 lockfiles keep package checksums in `[metadata]`, so the entries of the original `metadata`
+/// that belong to the batched packages are carried over, and their checksums are also attached to
+/// the packages, which is where the serializer takes them from for newer lockfile versions.
+///
+/// Packages in `context` are not depended upon by the batches, but are locked too, so that cargo
+/// keeps their versions when they are transitive dependencies of batched packages.
+///
+/// With `single_manifest`, the only batch is the root crate, which depends on the packages itself.
+pub fn synthesize<'a>(
+    version: ResolveVersion,
+    metadata: &Metadata,
+    batches: &'a [(String, Vec<Package>)],
+    single_manifest: bool,
+    context: impl IntoIterator<Item = &'a Package>,
+) -> Lockfile {
     let path_package = |name: &str, dependencies: Vec<Dependency>| Package {
         name: Name::from_str(name).expect("generated crate name should be valid"),
-        // Matches the version `cargo init` gives the generated manifests; a mismatch
+        // Matches the version `cargo_toml::manifest` gives the generated manifests; a mismatch
         // would only make cargo rewrite the entry on the first (non-frozen) resolution.
         version: Version::new(0, 1, 0),
         source: None,
@@ -23,31 +43,105 @@ pub fn synthesize(version: ResolveVersion, batches: &[(String, Vec<Package>)]) -
         replace: None,
     };
 
-    let batch_packages = batches
+    let batches = batches
+        .iter()
+        .map(|(name, packages)| (name, packages.iter().map(as_recorded).collect_vec()))
+        .collect_vec();
+    let context = context.into_iter().map(as_recorded).collect_vec();
+
+    // Registry packages without a checksum, like ones added from `[[patch.unused]]`, are left
+    // for cargo to resolve: it rejects lockfiles that omit a checksum the registry provides.
+    let locked = |p: &&Package| {
+        p.checksum.is_some() || !p.source.as_ref().is_some_and(SourceId::is_remote_registry)
+    };
+    let mut batch_packages = batches
         .iter()
-        .map(|(name, packages)| path_package(name, packages.iter().map(Dependency::from).collect()))
+        .map(|(name, packages)| {
+            path_package(
+                name,
+                packages
+                    .iter()
+                    .filter(locked)
+                    .map(Dependency::from)
+                    .collect(),
+            )
+        })
         .collect_vec();
-    let fake = path_package(
-        "fake",
-        batch_packages.iter().map(Dependency::from).collect(),
-    );
+    let root = if single_manifest {
+        assert_eq!(batch_packages.len(), 1, "single manifest is the only batch");
+        batch_packages.pop().expect("there is one batch")
+    } else {
+        path_package(
+            "fake",
+            batch_packages.iter().map(Dependency::from).collect(),
+        )
+    };
 
-    let packages = batches
+    let checksum_key = |p: &Package| MetadataKey::for_checksum(&Dependency::from(p));
+    let mut originals = batches
         .iter()
-        .flat_map(|(_, packages)| packages.iter().cloned())
+        .flat_map(|(_, packages)| packages)
+        .chain(&context)
+        .filter(locked)
+        .cloned()
+        .map(|mut p| {
+            // Matched by the full key, unlike the parser, which ignores the source.
+            if let Some(checksum) = metadata.get(&checksum_key(&p)).map(MetadataValue::checksum) {
+                p.checksum = checksum.ok();
+            }
+            p
+        })
+        .collect_vec();
+    // A dependency on a package missing from the lockfile makes cargo reject it as corrupt.
+    let present: HashSet<_> = originals
+        .iter()
+        .map(|p| identity(&Dependency::from(p)))
+        .collect();
+    for p in &mut originals {
+        p.dependencies.retain(|d| present.contains(&identity(d)));
+    }
+    let metadata = originals
+        .iter()
+        .filter_map(|p| {
+            let key = checksum_key(p);
+            metadata.get(&key).map(|value| (key, value.clone()))
+        })
+        .collect();
+
+    let packages = originals
+        .into_iter()
         .chain(batch_packages)
-        .chain(once(fake))
+        .chain(once(root))
         .collect();
 
     Lockfile {
         version,
         packages,
         root: None,
-        metadata: Default::default(),
+        metadata,
         patch: Default::default(),
     }
 }
 
+/// `package` with sources as cargo records them in the generated project's lockfile.
+///
+/// The generated project depends on packages of local registries and directories through
+/// registries of the same URL, replaced with the local sources, and cargo records the registries.
+fn as_recorded(package: &Package) -> Package {
+    let recorded = |source: &SourceId| match source.kind() {
+        SourceKind::LocalRegistry | SourceKind::Directory => {
+            SourceId::for_registry(source.url()).expect("local source URL should be a valid URL")
+        }
+        _ => source.clone(),
+    };
+    let mut package = package.clone();
+    package.source = package.source.as_ref().map(recorded);
+    for d in &mut package.dependencies {
+        d.source = d.source.as_ref().map(recorded);
+    }
+    package
+}
+
 /// Write the lockfile next to the generated project's root Cargo.toml.
 pub fn write_lockfile(dir: impl AsRef<Path>, lockfile: &Lockfile) -> Result<(), anyhow::Error> {
     std::fs::write(dir.as_ref().join("Cargo.lock"), lockfile.to_string()).with_context(|| {
@@ -115,11 +209,39 @@ checksum = "0123456789abcdef0123456789abcdef0123456789abcdef0123456789abcdef"
 dependencies = [
  "syn 1.0.109",
 ]
+"#;
+
+    const ORIGINAL_V1_LOCKFILE: &str = r#"
+[[package]]
+name = "memchr"
+version = "2.8.2"
+source = "registry+https://github.com/rust-lang/crates.io-index"
+
+[[package]]
+name = "memchr"
+version = "2.8.2"
+source = "registry+https://example.com/index"
+
+[[package]]
+name = "myproject"
+version = "1.0.0"
+dependencies = [
+ "memchr 2.8.2 (registry+https://example.com/index)",
+ "memchr 2.8.2 (registry+https://github.com/rust-lang/crates.io-index)",
+]
+
+[metadata]
+"checksum memchr 2.8.2 (registry+https://example.com/index)" = "0123456789abcdef0123456789abcdef0123456789abcdef0123456789abcdef"
+"checksum memchr 2.8.2 (registry+https://github.com/rust-lang/crates.io-index)" = "88904434abc2901f197fe8cc55f0445e7ded921dba5911dad2e2b39b48e663c4"
 "#;
 
     /// Non-local packages of the fixture, batched exactly as `cargo_lock_fetch::main` does.
     fn named_batches() -> Vec<(String, Vec<Package>)> {
-        let lockfile = Lockfile::from_str(ORIGINAL_LOCKFILE).expect("fixture should parse");
+        named_batches_of(ORIGINAL_LOCKFILE)
+    }
+
+    fn named_batches_of(original: &str) -> Vec<(String, Vec<Package>)> {
+        let lockfile = Lockfile::from_str(original).expect("fixture should parse");
         let packages = lockfile
             .packages
             .into_iter()
@@ -144,7 +266,7 @@ dependencies = [
     fn roundtrips_with_fake_root_depending_on_batches() {
         let batches = named_batches();
 
-        let synthesized = synthesize(ResolveVersion::V4, &batches);
+        let synthesized = synthesize(ResolveVersion::V4, &Default::default(), &batches, false, []);
         let reparsed = Lockfile::from_str(&synthesized.to_string())
             .expect("synthesized lockfile should parse");
 
@@ -170,11 +292,35 @@ dependencies = [
         );
     }
 
+    #[test]
+    fn single_manifest_root_depends_on_packages() {
+        let packages = named_batches()
+            .into_iter()
+            .flat_map(|(_, b)| b)
+            .collect_vec();
+        let batches = [("fake".to_string(), packages)];
+
+        let synthesized = synthesize(ResolveVersion::V4, &Default::default(), &batches, true, []);
+        let reparsed = Lockfile::from_str(&synthesized.to_string())
+            .expect("synthesized lockfile should parse");
+
+        let [fake] = find(&reparsed, "fake")[..] else {
+            panic!("exactly one fake package expected");
+        };
+        assert_eq!(fake.dependencies.len(), batches[0].1.len());
+        assert!(
+            reparsed
+                .packages
+                .iter()
+                .all(|p| p.name.as_str() != "batch1")
+        );
+    }
+
     #[test]
     fn copies_original_package_entries_verbatim() {
         let batches = named_batches();
 
-        let synthesized = synthesize(ResolveVersion::V4, &batches);
+        let synthesized = synthesize(ResolveVersion::V4, &Default::default(), &batches, false, []);
         let reparsed = Lockfile::from_str(&synthesized.to_string())
             .expect("synthesized lockfile should parse");
 
@@ -194,7 +340,7 @@ dependencies = [
     fn batch_entries_disambiguate_duplicate_versions() {
         let batches = named_batches();
 
-        let synthesized = synthesize(ResolveVersion::V4, &batches);
+        let synthesized = synthesize(ResolveVersion::V4, &Default::default(), &batches, false, []);
         let reparsed = Lockfile::from_str(&synthesized.to_string())
             .expect("synthesized lockfile should parse");
 
@@ -215,10 +361,145 @@ dependencies = [
     fn preserves_resolve_version() {
         let batches = named_batches();
 
-        let synthesized = synthesize(ResolveVersion::V3, &batches);
+        let synthesized = synthesize(ResolveVersion::V3, &Default::default(), &batches, false, []);
         let reparsed = Lockfile::from_str(&synthesized.to_string())
             .expect("synthesized lockfile should parse");
 
         assert_eq!(reparsed.version, ResolveVersion::V3);
     }
+
+    #[test]
+    fn preserves_v1_metadata_checksums() {
+        let original = Lockfile::from_str(ORIGINAL_V1_LOCKFILE).expect("fixture should parse");
+        let batches = named_batches_of(ORIGINAL_V1_LOCKFILE);
+
+        let synthesized = synthesize(ResolveVersion::V1, &original.metadata, &batches, false, []);
+        let reparsed = Lockfile::from_str(&synthesized.to_string())
+            .expect("synthesized lockfile should parse");
+
+        assert_eq!(reparsed.version, ResolveVersion::V1);
+        assert_eq!(reparsed.metadata, original.metadata);
+    }
+
+    #[test]
+    fn moves_v1_metadata_checksums_to_packages_by_source() {
+        let original = Lockfile::from_str(ORIGINAL_V1_LOCKFILE).expect("fixture should parse");
+        let batches = named_batches_of(ORIGINAL_V1_LOCKFILE);
+
+        let synthesized = synthesize(ResolveVersion::V4, &original.metadata, &batches, false, []);
+        let reparsed = Lockfile::from_str(&synthesized.to_string())
+            .expect("synthesized lockfile should parse");
+
+        assert!(reparsed.metadata.is_empty());
+        let checksums = find(&reparsed, "memchr")
+            .iter()
+            .map(|p| {
+                (
+                    p.source.as_ref().map(|s| s.url().to_string()),
+                    p.checksum.as_ref().map(|c| c.to_string()),
+                )
+            })
+            .sorted()
+            .collect_vec();
+        assert_eq!(
+            checksums,
+            vec![
+                (
+                    Some("https://example.com/index".to_string()),
+                    Some(
+                        "0123456789abcdef0123456789abcdef0123456789abcdef0123456789abcdef"
+                            .to_string()
+                    )
+                ),
+                (
+                    Some("https://github.com/rust-lang/crates.io-index".to_string()),
+                    Some(
+                        "88904434abc2901f197fe8cc55f0445e7ded921dba5911dad2e2b39b48e663c4"
+                            .to_string()
+                    )
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn leaves_registry_packages_without_checksum_unlocked() {
+        let mut batches = named_batches();
+        let unlocked = batches[0]
+            .1
+            .iter_mut()
+            .find(|p| p.name.as_str() == "memchr")
+            .expect("fixture should contain memchr");
+        unlocked.checksum = None;
+
+        let synthesized = synthesize(ResolveVersion::V4, &Default::default(), &batches, false, []);
+        let reparsed = Lockfile::from_str(&synthesized.to_string())
+            .expect("synthesized lockfile should parse");
+
+        assert!(find(&reparsed, "memchr").is_empty());
+        assert!(
+            reparsed
+                .packages
+                .iter()
+                .flat_map(|p| &p.dependencies)
+                .all(|d| d.name.as_str() != "memchr")
+        );
+    }
+
+    #[test]
+    fn drops_dependencies_on_filtered_out_packages() {
+        let batches = named_batches()
+            .into_iter()
+            .map(|(name, packages)| {
+                let packages = packages
+                    .into_iter()
+                    .filter(|p| p.name.as_str() != "memchr")
+                    .collect_vec();
+                (name, packages)
+            })
+            .collect_vec();
+
+        let synthesized = synthesize(ResolveVersion::V4, &Default::default(), &batches, false, []);
+        let reparsed = Lockfile::from_str(&synthesized.to_string())
+            .expect("synthesized lockfile should parse");
+
+        let [core2] = find(&reparsed, "core2")[..] else {
+            panic!("exactly one core2 package expected");
+        };
+        assert!(core2.dependencies.is_empty());
+    }
+
+    #[test]
+    fn locks_context_packages_without_depending_on_them() {
+        let (memchr, batches): (Vec<_>, Vec<_>) = named_batches()
+            .into_iter()
+            .flat_map(|(_, packages)| packages)
+            .partition(|p| p.name.as_str() == "memchr");
+        let batches = vec![("batch1".to_string(), batches)];
+
+        let synthesized = synthesize(
+            ResolveVersion::V4,
+            &Default::default(),
+            &batches,
+            false,
+            &memchr,
+        );
+        let reparsed = Lockfile::from_str(&synthesized.to_string())
+            .expect("synthesized lockfile should parse");
+
+        assert_eq!(find(&reparsed, "memchr").len(), 1);
+        let [core2] = find(&reparsed, "core2")[..] else {
+            panic!("exactly one core2 package expected");
+        };
+        assert_eq!(core2.dependencies.len(), 1);
+        let [batch] = find(&reparsed, "batch1")[..] else {
+            panic!("exactly one batch package expected");
+        };
+        assert!(
+            batch
+                .dependencies
+                .iter()
+                .all(|d| d.name.as_str() != "memchr")
+        );
+    }
 }
@@ -0,0 +1,428 @@
+//! Load input lockfiles and merge them into lockfiles of generated projects.
+//!
+//! Packages of many lockfiles can usually be fetched by a single generated project, but not
+//! always: cargo never selects two semver-compatible versions of a crate from the same source, and
+//! two versions of a crate that links a native library cannot coexist in one dependency graph
+//! either. Neither can be told from a lockfile alone, but every set of versions of a crate that
+//! appears in one input lockfile is known to resolve, so lockfiles are merged only as long as each
+//! crate's set of versions in the merged lockfile is one that appeared in a single input.
+
+use std::{
+    collections::{BTreeSet, HashMap, HashSet},
+    path::{Path, PathBuf},
+    str::FromStr as _,
+};
+
+use anyhow::Context as _;
+use cargo_lock::{Lockfile, Name, Package, SourceId, Version};
+use itertools::Itertools as _;
+use sha2::{Digest as _, Sha256};
+
+/// Source protocols of local registries and directories, which `cargo_lock` cannot parse.
+const LOCAL_SOURCE_PROTOCOLS: [&str; 2] = ["local-registry", "directory"];
+
+/// Load all lockfiles at `paths`, along with their [`master_branches`].
+pub fn load(
+    paths: &[impl AsRef<Path>],
+) -> Result<(Vec<Lockfile>, HashSet<SourceId>), anyhow::Error> {
+    let mut branches = HashSet::new();
+    let lockfiles = paths
+        .iter()
+        .map(|path| {
+            std::fs::read_to_string(path)
+                .map_err(anyhow::Error::from)
+                .and_then(|lockfile| {
+                    branches.extend(master_branches(&lockfile));
+                    parse(&lockfile)
+                })
+                .with_context(|| format!("could not load lock file {}", path.as_ref().display()))
+        })
+        .try_collect()?;
+    Ok((lockfiles, branches))
+}
+
+/// SHA-256 digest of the contents of all lockfiles at `paths`, sorted by path.
+pub fn digest(paths: &[impl AsRef<Path>]) -> Result<String, anyhow::Error> {
+    let mut hasher = Sha256::new();
+    for path in paths.iter().map(AsRef::as_ref).sorted() {
+        let lockfile = std::fs::read(path)
+            .with_context(|| format!("could not read lock file {}", path.display()))?;
+        hasher.update(lockfile);
+    }
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// Parse a lockfile, including packages from local registries and directories.
+///
+/// Their source ids are disguised as `path+` ones, with the protocol in the query, for `cargo_lock`
+/// to parse them, and restored afterwards.
+pub fn parse(lockfile: &str) -> Result<Lockfile, anyhow::Error> {
+    let mut lockfile = Lockfile::from_str(&disguise_local_sources(lockfile))?;
+    let restore = |source: &mut Option<SourceId>| {
+        if let Some(s) = source.as_mut() {
+            *s = restore_local_source(s);
+        }
+    };
+    for p in &mut lockfile.packages {
+        restore(&mut p.source);
+        p.dependencies
+            .iter_mut()
+            .for_each(|d| restore(&mut d.source));
+        p.replace.iter_mut().for_each(|d| restore(&mut d.source));
+    }
+    for d in &mut lockfile.patch.unused {
+        restore(&mut d.source);
+    }
+    Ok(lockfile)
+}
+
+fn disguise_local_sources(lockfile: &str) -> String {
+    LOCAL_SOURCE_PROTOCOLS
+        .iter()
+        .fold(lockfile.to_string(), |lockfile, protocol| {
+            let prefix = format!("{protocol}+");
+            let mut disguised = String::with_capacity(lockfile.len());
+            let mut rest = lockfile.as_str();
+            while let Some(start) = rest.find(&prefix) {
+                let (before, source) = rest.split_at(start);
+                disguised.push_str(before);
+                // Source ids are whole strings or parenthesized in dependency references.
+                if !before.ends_with(['"', '(']) {
+                    disguised.push_str(&prefix);
+                    rest = &source[prefix.len()..];
+                    continue;
+                }
+                let end = source.find(['"', ')']).unwrap_or(source.len());
+                disguised.push_str(&format!("path+{}?{protocol}", &source[prefix.len()..end]));
+                rest = &source[end..];
+            }
+            disguised.push_str(rest);
+            disguised
+        })
+}
+
+/// Restore a disguised local source, local sources without a file URL are left as path sources.
+fn restore_local_source(source: &SourceId) -> SourceId {
+    let url = source.url();
+    let restored = url
+        .query()
+        .filter(|q| LOCAL_SOURCE_PROTOCOLS.contains(q))
+        .zip(url.to_file_path().ok())
+        .and_then(|(protocol, path)| match protocol {
+            "local-registry" => SourceId::for_local_registry(&path).ok(),
+            _ => SourceId::for_directory(&path).ok(),
+        });
+    restored.unwrap_or_else(|| source.clone())
+}
+
+/// Git sources of `lockfile` which name the `master` branch explicitly, without precise revisions.
+///
+/// `cargo_lock` parses git sources without a reference as the `master` branch too, but cargo takes
+/// the repository's default branch for those, so only the lockfile text tells them apart.
+pub fn master_branches(lockfile: &str) -> HashSet<SourceId> {
+    lockfile
+        .match_indices("git+")
+        // Source ids are whole strings or parenthesized in dependency references.
+        .filter(|(start, _)| lockfile[..*start].ends_with(['"', '(']))
+        .filter_map(|(start, _)| {
+            let source = &lockfile[start..];
+            let source = &source[..source.find(['"', ')']).unwrap_or(source.len())];
+            let (_, query) = source.split('#').next()?.split_once('?')?;
+            // The last reference wins, as it does when `cargo_lock` parses the source.
+            let reference = query
+                .split('&')
+                .filter_map(|pair| pair.split_once('='))
+                .rfind(|(key, _)| ["branch", "ref", "rev", "tag"].contains(key))?;
+            matches!(reference, ("branch" | "ref", "master"))
+                .then(|| SourceId::from_url(source).ok())?
+        })
+        .map(|source| source.with_precise(None))
+        .collect()
+}
+
+/// Find all `Cargo.lock` files under `dir`, as [`find_files`] does.
+pub fn discover(dir: impl AsRef<Path>) -> Result<Vec<PathBuf>, anyhow::Error> {
+    find_files(dir.as_ref(), "Cargo.lock")
+}
+
+/// Find all files named `file_name` under `dir`, sorted by path.
+///
+/// Files excluded by `.gitignore` (even outside of a git repository), hidden directories and
+/// `target` directories are skipped.
+pub fn find_files(dir: &Path, file_name: &str) -> Result<Vec<PathBuf>, anyhow::Error> {
+    ignore::WalkBuilder::new(dir)
+        .require_git(false)
+        .filter_entry(|entry| {
+            !(entry.file_type().is_some_and(|t| t.is_dir()) && entry.file_name() == "target")
+        })
+        .build()
+        .filter_map_ok(|entry| {
+            (entry.file_type().is_some_and(|t| t.is_file()) && entry.file_name() == file_name)
+                .then(|| entry.into_path())
+        })
+        .map(|path| path.with_context(|| format!("failed to search {}", dir.display())))
+        .process_results(|paths| paths.sorted().collect_vec())
+}
+
+/// Exact identity of a package in lockfiles.
+pub type PackageKey = (Name, Version, Option<SourceId>);
+
+pub fn package_key(p: &Package) -> PackageKey {
+    (p.name.clone(), p.version.clone(), p.source.clone())
+}
+
+/// Merge lockfiles greedily into as few lockfiles as possible, each of which cargo can resolve.
+///
+/// Identical `(name, version, source)` packages are only kept once, the highest resolve version of
+/// the merged inputs is used.
+pub fn merge(lockfiles: impl IntoIterator<Item = Lockfile>) -> Vec<Lockfile> {
+    let mut merged: Vec<Lockfile> = vec![];
+    for lockfile in lockfiles {
+        match merged.iter_mut().find(|m| compatible(m, &lockfile)) {
+            Some(m) => absorb(m, lockfile),
+            None => merged.push(lockfile),
+        }
+    }
+    merged
+}
+
+/// Sets of versions of each crate, by name and source (without the precise git revision).
+fn versions(lockfile: &Lockfile) -> HashMap<(&Name, SourceId), BTreeSet<&Version>> {
+    lockfile
+        .packages
+        .iter()
+        .filter_map(|p| Some(((&p.name, p.source.as_ref()?.with_precise(None)), &p.version)))
+        .into_grouping_map()
+        .collect()
+}
+
+fn compatible(a: &Lockfile, b: &Lockfile) -> bool {
+    let a = versions(a);
+    versions(b)
+        .iter()
+        .all(|(key, b)| a.get(key).is_none_or(|a| a.is_subset(b) || b.is_subset(a)))
+}
+
+fn absorb(into: &mut Lockfile, from: Lockfile) {
+    into.version = into.version.max(from.version);
+    let known: BTreeSet<_> = into.packages.iter().map(package_key).collect();
+    into.packages.extend(
+        from.packages
+            .into_iter()
+            .filter(|p| !known.contains(&package_key(p))),
+    );
+    for (key, value) in from.metadata {
+        into.metadata.entry(key).or_insert(value);
+    }
+    for unused in from.patch.unused {
+        if !into.patch.unused.contains(&unused) {
+            into.patch.unused.push(unused);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::str::FromStr as _;
+
+    use cargo_lock::{Lockfile, ResolveVersion};
+    use itertools::Itertools as _;
+
+    use super::{digest, discover, master_branches, merge, parse};
+
+    fn lockfile(version: u32, packages: &[(&str, &str)]) -> Lockfile {
+        let packages = packages
+            .iter()
+            .map(|(name, version)| {
+                format!(
+                    indoc::indoc! {r#"
+                        [[package]]
+                        name = "{}"
+                        version = "{}"
+                        source = "registry+https://github.com/rust-lang/crates.io-index"
+                    "#},
+                    name, version
+                )
+            })
+            .join("\n");
+        Lockfile::from_str(&format!("version = {version}\n\n{packages}"))
+            .expect("fixture should parse")
+    }
+
+    fn contents(lockfile: &Lockfile) -> Vec<String> {
+        lockfile
+            .packages
+            .iter()
+            .map(|p| format!("{} {}", p.name, p.version))
+            .sorted()
+            .collect_vec()
+    }
+
+    #[test]
+    fn merges_and_dedupes_identical_packages() {
+        let merged = merge([
+            lockfile(3, &[("memchr", "2.8.2"), ("syn", "2.0.118")]),
+            lockfile(4, &[("memchr", "2.8.2"), ("libc", "0.2.180")]),
+        ]);
+
+        let [merged] = &merged[..] else {
+            panic!("exactly one merged lockfile expected");
+        };
+        assert_eq!(merged.version, ResolveVersion::V4);
+        assert_eq!(
+            contents(merged),
+            vec!["libc 0.2.180", "memchr 2.8.2", "syn 2.0.118"]
+        );
+    }
+
+    #[test]
+    fn keeps_version_sets_seen_in_one_lockfile_together() {
+        let merged = merge([
+            lockfile(4, &[("syn", "2.0.118")]),
+            lockfile(4, &[("syn", "1.0.109"), ("syn", "2.0.118")]),
+        ]);
+
+        assert_eq!(merged.len(), 1);
+        assert_eq!(contents(&merged[0]), vec!["syn 1.0.109", "syn 2.0.118"]);
+    }
+
+    #[test]
+    fn splits_unseen_version_combinations() {
+        let merged = merge([
+            lockfile(4, &[("serde", "1.0.100"), ("memchr", "2.8.2")]),
+            lockfile(4, &[("serde", "1.0.200")]),
+            lockfile(4, &[("serde", "1.0.200"), ("memchr", "2.8.2")]),
+        ]);
+
+        assert_eq!(
+            merged.iter().map(contents).collect_vec(),
+            vec![
+                vec!["memchr 2.8.2", "serde 1.0.100"],
+                vec!["memchr 2.8.2", "serde 1.0.200"],
+            ]
+        );
+    }
+
+    #[test]
+    fn discovers_lockfiles_skipping_ignored_and_target() {
+        let dir = temp_dir::TempDir::new().expect("temp dir should be created");
+        for path in ["a", "b/c", "b/target", "target", "ignored"] {
+            std::fs::create_dir_all(dir.path().join(path)).expect("dir should be created");
+        }
+        for path in ["a", "b/c", "b/target", "target", "ignored", "."] {
+            std::fs::write(dir.path().join(path).join("Cargo.lock"), "")
+                .expect("file should be written");
+        }
+        std::fs::write(dir.path().join(".gitignore"), "/ignored\n")
+            .expect("file should be written");
+
+        let found = discover(dir.path()).expect("discovery should succeed");
+
+        assert_eq!(
+            found
+                .iter()
+                .map(|p| p.strip_prefix(dir.path()).expect("path should be in dir"))
+                .collect_vec(),
+            ["Cargo.lock", "a/Cargo.lock", "b/c/Cargo.lock"].map(std::path::Path::new)
+        );
+    }
+
+    #[test]
+    fn digests_lockfile_contents_in_path_order() {
+        let dir = temp_dir::TempDir::new().expect("temp dir should be created");
+        let (a, b) = (dir.path().join("a.lock"), dir.path().join("b.lock"));
+        std::fs::write(&a, "version = 4\n").expect("file should be written");
+        std::fs::write(&b, "version = 3\n").expect("file should be written");
+
+        let digests = [[&a, &b], [&b, &a]].map(|paths| digest(&paths).expect("files are readable"));
+
+        assert_eq!(digests[0], digests[1]);
+        std::fs::write(&b, "version = 4\n").expect("file should be written");
+        assert_ne!(digest(&[&a, &b]).expect("files are readable"), digests[0]);
+    }
+
+    #[test]
+    fn parses_local_registry_and_directory_sources() {
+        let lockfile = parse(indoc::indoc! {r#"
+            version = 3
+
+            [[package]]
+            name = "itoa"
+            version = "1.0.11"
+            source = "local-registry+file:///srv/registry"
+            checksum = "49f1f14873335454500d59611f1cf4a4b0f786f9ac11f4312a78e4cf2566695b"
+
+            [[package]]
+            name = "itoa"
+            version = "1.0.18"
+            source = "directory+file:///srv/vendor"
+            checksum = "8f42a60cbdf9a97f5d2305f08a87dc4e09308d1276d28c869c684d7777685682"
+
+            [[package]]
+            name = "package"
+            version = "0.1.0"
+            dependencies = [
+             "itoa 1.0.11 (local-registry+file:///srv/registry)",
+             "itoa 1.0.18 (directory+file:///srv/vendor)",
+            ]
+        "#})
+        .expect("lockfile should parse");
+
+        let sources = lockfile
+            .packages
+            .iter()
+            .flat_map(|p| {
+                p.source
+                    .iter()
+                    .chain(p.dependencies.iter().filter_map(|d| d.source.as_ref()))
+            })
+            .map(|s| s.to_string())
+            .collect_vec();
+        assert_eq!(
+            sources,
+            vec![
+                "local-registry+file:///srv/registry",
+                "directory+file:///srv/vendor",
+                "local-registry+file:///srv/registry",
+                "directory+file:///srv/vendor",
+            ]
+        );
+    }
+
+    #[test]
+    fn finds_git_sources_with_an_explicit_master_branch() {
+        let branches = master_branches(indoc::indoc! {r#"
+            [[package]]
+            name = "explicit"
+            version = "0.1.0"
+            source = "git+https://example.com/explicit?branch=master#1234567"
+
+            [[package]]
+            name = "legacy"
+            version = "0.1.0"
+            source = "git+https://example.com/legacy?ref=master"
+
+            [[package]]
+            name = "implicit"
+            version = "0.1.0"
+            source = "git+https://example.com/implicit#1234567"
+
+            [[package]]
+            name = "other"
+            version = "0.1.0"
+            source = "git+https://example.com/other?branch=main"
+        "#});
+
+        assert_eq!(
+            branches
+                .iter()
+                .map(|s| s.to_string())
+                .sorted()
+                .collect_vec(),
+            vec![
+                "git+https://example.com/explicit",
+                "git+https://example.com/legacy",
+            ]
+        );
+    }
+}
@@ -1,16 +1,28 @@
 mod batches;
 mod cargo;
 mod cargo_config_toml;
+mod cargo_failures;
+mod cargo_home;
 mod cargo_lock_fetch;
 mod cargo_toml;
+mod check;
 mod cli;
+mod dependency_graph;
+mod download;
+mod local_packages;
 mod lockfile_synth;
+mod lockfiles;
+mod package_filter;
 mod registry_aliases;
+mod report;
+mod vendor;
+mod verify;
 
 use std::process::ExitCode;
 
 use clap::{CommandFactory, Parser as _, error::ErrorKind};
 
+use crate::cargo_lock_fetch::{Failure, FailureError};
 use crate::cli::{CargoLockFetch, CargoLockFetchCli, Cli};
 
 shadow_rs::shadow!(build);
@@ -28,7 +40,16 @@ fn main() -> ExitCode {
     match cargo_lock_fetch::main(&sub) {
         Ok(status) => status,
         Err(error) => {
-            exit_cli_error(quiet, ErrorKind::Io, &format!("{error:?}"));
+            let code = error
+                .chain()
+                .find_map(|e| e.downcast_ref::<FailureError>())
+                .map_or(Failure::OTHER_EXIT_CODE, |e| e.failure.exit_code());
+            if !quiet {
+                let _ = CargoLockFetchCli::command()
+                    .error(ErrorKind::Io, format!("{error:?}"))
+                    .print();
+            }
+            ExitCode::from(code)
         }
     }
 }
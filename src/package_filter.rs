@@ -0,0 +1,240 @@
+//! Filters selecting lockfile packages by name, version or source.
+
+use std::str::FromStr;
+
+use cargo_lock::{Package, Version, package::SourceKind};
+
+/// A single `--include`/`--exclude` filter.
+#[derive(Clone, Debug)]
+pub enum PackageFilter {
+    /// `<glob>` or `<glob>@<version>`: crate name matching a glob, optionally of an exact version.
+    Name(glob::Pattern, Option<Version>),
+    /// `source=<kind>`: kind of source, one of [`SourceFilterKind`].
+    Source(SourceFilterKind),
+    /// `registry=<url>`: remote registry index, git or sparse.
+    Registry(String),
+    /// `git=<url>`: git repository.
+    Git(String),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SourceFilterKind {
+    /// `registry`: any remote registry, git or sparse.
+    Registry,
+    /// `sparse`: sparse registry.
+    Sparse,
+    /// `git`: git repository.
+    Git,
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum FilterError {
+    #[error("invalid crate name pattern {0:?}: {1}")]
+    Pattern(String, glob::PatternError),
+    #[error("invalid version {0:?}: {1}")]
+    Version(String, String),
+    #[error("unknown source kind {0:?}, expected one of: registry, sparse, git")]
+    SourceKind(String),
+}
+
+impl FromStr for PackageFilter {
+    type Err = FilterError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s.split_once('=') {
+            Some(("source", kind)) => PackageFilter::Source(match kind {
+                "registry" => SourceFilterKind::Registry,
+                "sparse" => SourceFilterKind::Sparse,
+                "git" => SourceFilterKind::Git,
+                kind => Err(FilterError::SourceKind(kind.to_string()))?,
+            }),
+            Some(("registry", url)) => PackageFilter::Registry(normalize_url(url)),
+            Some(("git", url)) => PackageFilter::Git(normalize_url(url)),
+            _ => {
+                let (name, version) = match s.split_once('@') {
+                    Some((name, version)) => (name, Some(version)),
+                    None => (s, None),
+                };
+                let pattern = glob::Pattern::new(name)
+                    .map_err(|e| FilterError::Pattern(name.to_string(), e))?;
+                let version = version
+                    .map(|v| {
+                        Version::parse(v)
+                            .map_err(|e| FilterError::Version(v.to_string(), e.to_string()))
+                    })
+                    .transpose()?;
+                PackageFilter::Name(pattern, version)
+            }
+        })
+    }
+}
+
+impl PackageFilter {
+    pub fn matches(&self, package: &Package) -> bool {
+        let source = package.source.as_ref();
+        let url = || source.map(|s| normalize_url(s.url().as_str()));
+        match self {
+            PackageFilter::Name(pattern, version) => {
+                pattern.matches(package.name.as_str())
+                    && version.as_ref().is_none_or(|v| *v == package.version)
+            }
+            PackageFilter::Source(kind) => match (kind, source.map(|s| s.kind())) {
+                (SourceFilterKind::Registry, Some(k)) => {
+                    matches!(k, SourceKind::Registry | SourceKind::SparseRegistry)
+                }
+                (SourceFilterKind::Sparse, Some(k)) => *k == SourceKind::SparseRegistry,
+                (SourceFilterKind::Git, Some(k)) => matches!(k, SourceKind::Git(_)),
+                (_, None) => false,
+            },
+            PackageFilter::Registry(registry) => {
+                source.is_some_and(|s| s.is_remote_registry()) && url().as_ref() == Some(registry)
+            }
+            PackageFilter::Git(repository) => {
+                source.is_some_and(|s| s.is_git()) && url().as_ref() == Some(repository)
+            }
+        }
+    }
+}
+
+/// Decide if a package passes `--include` and `--exclude` filters.
+///
+/// A package is selected if it matches any of `include` (or `include` is empty) and none of
+/// `exclude`.
+pub fn is_selected(
+    include: &[PackageFilter],
+    exclude: &[PackageFilter],
+    package: &Package,
+) -> bool {
+    (include.is_empty() || include.iter().any(|f| f.matches(package)))
+        && !exclude.iter().any(|f| f.matches(package))
+}
+
+/// Make URLs comparable regardless of the protocol prefix used in Cargo.lock and trailing slashes.
+fn normalize_url(url: &str) -> String {
+    let url = url
+        .strip_prefix("sparse+")
+        .or_else(|| url.strip_prefix("registry+"))
+        .or_else(|| url.strip_prefix("git+"))
+        .unwrap_or(url);
+    url.trim_end_matches('/').to_string()
+}
+
+#[cfg(test)]
+mod test {
+    use std::str::FromStr as _;
+
+    use cargo_lock::{Lockfile, Package};
+    use itertools::Itertools as _;
+
+    use super::{PackageFilter, is_selected};
+
+    const LOCKFILE: &str = r#"
+version = 4
+
+[[package]]
+name = "myproject"
+version = "1.0.0"
+
+[[package]]
+name = "private-utils"
+version = "0.3.0"
+source = "git+https://git.example.com/private-utils?rev=0123456#0123456789abcdef0123456789abcdef01234567"
+
+[[package]]
+name = "serde"
+version = "1.0.200"
+source = "registry+https://github.com/rust-lang/crates.io-index"
+checksum = "ddc6f9cc94d67c0e21aaf7eda3a010fd3af78ebf6e096aa6e2e13c79749cce4f"
+
+[[package]]
+name = "serde_json"
+version = "1.0.100"
+source = "sparse+https://my.registry/index/"
+checksum = "0123456789abcdef0123456789abcdef0123456789abcdef0123456789abcdef"
+
+[[package]]
+name = "syn"
+version = "1.0.109"
+source = "registry+https://github.com/rust-lang/crates.io-index"
+checksum = "deadbeefdeadbeefdeadbeefdeadbeefdeadbeefdeadbeefdeadbeefdeadbeef"
+
+[[package]]
+name = "syn"
+version = "2.0.118"
+source = "registry+https://github.com/rust-lang/crates.io-index"
+checksum = "cafebabecafebabecafebabecafebabecafebabecafebabecafebabecafebabe"
+"#;
+
+    fn filters(specs: &[&str]) -> Vec<PackageFilter> {
+        specs
+            .iter()
+            .map(|s| PackageFilter::from_str(s).expect("filter should parse"))
+            .collect_vec()
+    }
+
+    fn selected(include: &[&str], exclude: &[&str]) -> Vec<String> {
+        let (include, exclude) = (filters(include), filters(exclude));
+        Lockfile::from_str(LOCKFILE)
+            .expect("fixture should parse")
+            .packages
+            .iter()
+            .filter(|p: &&Package| is_selected(&include, &exclude, p))
+            .map(|p| format!("{}@{}", p.name, p.version))
+            .collect_vec()
+    }
+
+    #[test]
+    fn no_filters_select_everything() {
+        assert_eq!(selected(&[], &[]).len(), 6);
+    }
+
+    #[test]
+    fn name_globs_and_versions() {
+        assert_eq!(
+            selected(&["serde*", "syn@1.0.109"], &[]),
+            vec!["serde@1.0.200", "serde_json@1.0.100", "syn@1.0.109"]
+        );
+        assert_eq!(
+            selected(&["s*"], &["*json", "syn@2.0.118"]),
+            vec!["serde@1.0.200", "syn@1.0.109"]
+        );
+    }
+
+    #[test]
+    fn source_kinds() {
+        assert_eq!(
+            selected(&[], &["source=registry"]),
+            vec!["myproject@1.0.0", "private-utils@0.3.0"]
+        );
+        assert_eq!(selected(&["source=git"], &[]), vec!["private-utils@0.3.0"]);
+        assert_eq!(
+            selected(&["source=sparse"], &[]),
+            vec!["serde_json@1.0.100"]
+        );
+    }
+
+    #[test]
+    fn source_urls() {
+        assert_eq!(
+            selected(&["registry=https://my.registry/index"], &[]),
+            vec!["serde_json@1.0.100"]
+        );
+        assert_eq!(
+            selected(&["registry=sparse+https://my.registry/index/"], &[]),
+            vec!["serde_json@1.0.100"]
+        );
+        assert_eq!(
+            selected(&["git=https://git.example.com/private-utils"], &[]),
+            vec!["private-utils@0.3.0"]
+        );
+    }
+
+    #[test]
+    fn invalid_filters_are_rejected() {
+        assert!(PackageFilter::from_str("source=svn").is_err());
+        // Local crates are never fetched, so there is nothing to filter.
+        assert!(PackageFilter::from_str("source=path").is_err());
+        assert!(PackageFilter::from_str("syn@latest").is_err());
+        assert!(PackageFilter::from_str("[syn").is_err());
+    }
+}
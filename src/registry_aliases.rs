@@ -1,11 +1,24 @@
-use std::collections::BTreeMap;
+use std::{collections::BTreeMap, path::PathBuf};
+
+/// A local registry or directory source, standing in for a registry.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum LocalSource {
+    LocalRegistry(PathBuf),
+    Directory(PathBuf),
+}
 
 /// Provide distinct aliases for cargo registry URIs to be used in .cargo/config.toml.
-pub struct RegistryAliases(BTreeMap<String, String>);
+pub struct RegistryAliases {
+    aliases: BTreeMap<String, String>,
+    local: BTreeMap<String, LocalSource>,
+}
 
 impl RegistryAliases {
     pub fn new() -> Self {
-        Self(BTreeMap::new())
+        Self {
+            aliases: BTreeMap::new(),
+            local: BTreeMap::new(),
+        }
     }
 
     /// Get alias for a cargo registry.
@@ -13,16 +26,31 @@ impl RegistryAliases {
     /// Equal aliases are always returned for equal URIs. Distinct aliases are always returned for
     /// distinct URIs.
     pub fn get_alias(&mut self, uri: String) -> &str {
-        let num = self.0.len() + 1;
-        self.0.entry(uri).or_insert_with(|| format!("reg{num}"))
+        let num = self.aliases.len() + 1;
+        self.aliases
+            .entry(uri)
+            .or_insert_with(|| format!("reg{num}"))
+    }
+
+    /// Get alias for a cargo registry which is to be replaced by the `local` source.
+    pub fn get_local_alias(&mut self, uri: String, local: LocalSource) -> &str {
+        self.local.insert(uri.clone(), local);
+        self.get_alias(uri)
     }
 
     /// Iterate all aliases as (alias, uri) pairs.
     pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
-        self.0
+        self.aliases
             .iter()
             .map(|(uri, alias)| (alias.as_ref(), uri.as_ref()))
     }
+
+    /// Iterate aliases of registries replaced by local sources as (alias, uri, source) triples.
+    pub fn local_sources(&self) -> impl Iterator<Item = (&str, &str, &LocalSource)> {
+        self.local
+            .iter()
+            .map(|(uri, local)| (self.aliases[uri].as_ref(), uri.as_ref(), local))
+    }
 }
 
 #[cfg(test)]
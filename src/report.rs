@@ -0,0 +1,50 @@
+//! Machine-readable report of a run, written with `--report`.
+
+use std::path::Path;
+
+use anyhow::Context as _;
+use cargo_lock::{Checksum, Name, SourceId, Version};
+
+use crate::local_packages::LocalPackage;
+
+#[derive(serde::Serialize, Debug)]
+pub struct Report {
+    /// Exit code of the last cargo invocation, `None` if cargo was not run or was killed.
+    pub cargo_status: Option<i32>,
+    pub packages: Vec<PackageReport>,
+    /// Packages that could not be fetched with `--keep-going`.
+    pub failed_packages: Vec<FailedPackage>,
+    /// Workspace members and path dependencies, which are not fetched.
+    pub local_packages: Vec<LocalPackage>,
+}
+
+#[derive(serde::Serialize, Debug)]
+pub struct PackageReport {
+    pub name: Name,
+    pub version: Version,
+    pub source: Option<SourceId>,
+    pub checksum: Option<Checksum>,
+    /// Generated project the package was fetched by, relative to the temporary directory.
+    pub project: String,
+    /// Sub-crate of the generated project depending on the package.
+    pub batch: String,
+    /// Alias of the package's registry in `.cargo/config.toml`, `None` for the default registry
+    /// and for other sources.
+    pub registry: Option<String>,
+    /// Whether the package was in cargo's cache before the run.
+    pub cached: bool,
+}
+
+#[derive(serde::Serialize, Debug, Clone)]
+pub struct FailedPackage {
+    pub name: Name,
+    pub version: Version,
+    pub source: Option<SourceId>,
+    pub reason: String,
+}
+
+pub fn write(path: impl AsRef<Path>, report: &Report) -> Result<(), anyhow::Error> {
+    let json = serde_json::to_string_pretty(report).expect("report should serialize to json");
+    std::fs::write(path.as_ref(), json + "\n")
+        .with_context(|| format!("Failed to write report to {:?}", path.as_ref().as_os_str()))
+}
@@ -0,0 +1,260 @@
+//! Native vendoring of registry crates, without cargo.
+//!
+//! Crates are unpacked from cargo's registry cache into the same layout `cargo vendor` produces,
+//! including `.cargo-checksum.json` files, so that cargo accepts the directory as a `directory`
+//! source.
+
+use std::{
+    collections::{BTreeMap, HashSet},
+    path::{Component, Path, PathBuf},
+};
+
+use anyhow::{Context as _, anyhow};
+use cargo_lock::Package;
+use itertools::Itertools as _;
+use sha2::{Digest as _, Sha256};
+
+use crate::cargo_home;
+
+/// Files which `cargo vendor` leaves out, as they are irrelevant to builds.
+const SKIPPED_FILES: [&str; 3] = [".gitattributes", ".gitignore", ".git"];
+
+/// Contents of `.cargo-checksum.json`.
+#[derive(serde::Serialize, serde::Deserialize)]
+struct Checksums {
+    files: BTreeMap<String, String>,
+    package: Option<String>,
+}
+
+/// Vendor registry `packages`, whose `.crate` files are in the registry cache of `cargo_home`,
+/// into `vendor_dir`.
+///
+/// Like `cargo vendor`, crates vendored before are removed unless they are still needed, and the
+/// highest version of every crate goes to a directory named just after the crate, unless
+/// `versioned_dirs` is set. Returns the configuration which makes cargo use `vendor_dir`.
+pub fn vendor<'a>(
+    cargo_home: &Path,
+    packages: impl IntoIterator<Item = &'a Package>,
+    vendor_dir: &Path,
+    versioned_dirs: bool,
+) -> Result<String, anyhow::Error> {
+    let packages = packages
+        .into_iter()
+        .unique_by(|p| (&p.name, &p.version, &p.source))
+        .collect_vec();
+    if let Some(p) = packages
+        .iter()
+        .duplicates_by(|p| (&p.name, &p.version))
+        .next()
+    {
+        Err(anyhow!(
+            "found duplicate version of package `{} v{}` vendored from two sources",
+            p.name,
+            p.version
+        ))?;
+    }
+    let latest = packages
+        .iter()
+        .into_grouping_map_by(|p| &p.name)
+        .max_by_key(|_, p| &p.version);
+
+    std::fs::create_dir_all(vendor_dir)
+        .with_context(|| format!("failed to create {}", vendor_dir.display()))?;
+    let mut stale: HashSet<_> = std::fs::read_dir(vendor_dir)
+        .with_context(|| format!("failed to read {}", vendor_dir.display()))?
+        .filter_map(Result::ok)
+        .map(|entry| entry.path())
+        .filter(|path| path.join(".cargo-checksum.json").is_file())
+        .collect();
+    for p in &packages {
+        let dir = if !versioned_dirs && latest[&p.name].version == p.version {
+            vendor_dir.join(p.name.as_str())
+        } else {
+            vendor_dir.join(format!("{}-{}", p.name, p.version))
+        };
+        stale.remove(&dir);
+        unpack(cargo_home, p, &dir)
+            .with_context(|| format!("failed to vendor {} {}", p.name, p.version))?;
+    }
+    for dir in stale {
+        std::fs::remove_dir_all(&dir)
+            .with_context(|| format!("failed to remove {}", dir.display()))?;
+    }
+
+    let sources = packages
+        .iter()
+        .filter_map(|p| p.source.as_ref())
+        .unique()
+        .map(|source| {
+            if source.is_default_registry() {
+                "[source.crates-io]\n".to_string()
+            } else {
+                format!("[source.\"{source}\"]\nregistry = \"{source}\"\n")
+            }
+        })
+        .map(|header| format!("{header}replace-with = \"vendored-sources\"\n"))
+        .join("\n");
+    Ok(format!(
+        "{sources}\n[source.vendored-sources]\ndirectory = \"{}\"\n",
+        vendor_dir.display()
+    ))
+}
+
+/// Unpack the `.crate` file of `package` into `dir` and write its `.cargo-checksum.json`.
+///
+/// Nothing is done if `dir` already holds the same package.
+fn unpack(cargo_home: &Path, package: &Package, dir: &Path) -> Result<(), anyhow::Error> {
+    let checksum = package
+        .checksum
+        .as_ref()
+        .ok_or_else(|| anyhow!("package has no checksum"))?
+        .to_string();
+    let checksums_path = dir.join(".cargo-checksum.json");
+    let existing = std::fs::read(&checksums_path)
+        .ok()
+        .and_then(|existing| serde_json::from_slice::<Checksums>(&existing).ok());
+    if existing.is_some_and(|c| c.package.as_ref() == Some(&checksum)) {
+        return Ok(());
+    }
+    if dir.exists() {
+        std::fs::remove_dir_all(dir)?;
+    }
+
+    let crate_path = cargo_home::cached_crate_path(cargo_home, package)
+        .ok_or_else(|| anyhow!("package is not from a registry"))?;
+    let crate_file = std::fs::File::open(&crate_path)
+        .with_context(|| format!("failed to open {}", crate_path.display()))?;
+    let mut archive = tar::Archive::new(flate2::read::GzDecoder::new(crate_file));
+    let prefix = PathBuf::from(format!("{}-{}", package.name, package.version));
+    let mut files = BTreeMap::new();
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        if entry.header().entry_type() != tar::EntryType::Regular {
+            continue;
+        }
+        let path = entry.path()?.into_owned();
+        let relative = path
+            .strip_prefix(&prefix)
+            .ok()
+            .filter(|r| r.components().all(|c| matches!(c, Component::Normal(_))))
+            .ok_or_else(|| anyhow!("unexpected path in crate archive: {}", path.display()))?;
+        if relative
+            .file_name()
+            .is_some_and(|name| SKIPPED_FILES.iter().any(|s| name == *s))
+        {
+            continue;
+        }
+        let destination = dir.join(relative);
+        std::fs::create_dir_all(destination.parent().expect("file is in a directory"))?;
+        entry.unpack(&destination)?;
+        let contents = std::fs::read(&destination)?;
+        files.insert(
+            relative
+                .components()
+                .map(|c| c.as_os_str().to_string_lossy())
+                .join("/"),
+            format!("{:x}", Sha256::digest(contents)),
+        );
+    }
+    let checksums = Checksums {
+        files,
+        package: Some(checksum),
+    };
+    std::fs::write(checksums_path, serde_json::to_string(&checksums)?)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::{io::Write as _, str::FromStr as _};
+
+    use cargo_lock::Lockfile;
+    use itertools::Itertools as _;
+    use sha2::{Digest as _, Sha256};
+
+    use super::vendor;
+
+    /// A gzipped `.crate` archive of crate `name` with `files`.
+    fn crate_archive(name: &str, files: &[(&str, &str)]) -> Vec<u8> {
+        let mut archive = tar::Builder::new(flate2::write::GzEncoder::new(
+            vec![],
+            flate2::Compression::default(),
+        ));
+        for (path, contents) in files {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(contents.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            archive
+                .append_data(&mut header, format!("{name}/{path}"), contents.as_bytes())
+                .expect("file should be archived");
+        }
+        archive
+            .into_inner()
+            .and_then(|gz| gz.finish())
+            .expect("archive should be finished")
+    }
+
+    #[test]
+    fn vendors_like_cargo_vendor() {
+        let home = temp_dir::TempDir::new().expect("temp dir should be created");
+        let cache = home
+            .path()
+            .join("registry/cache/index.crates.io-1949cf8c6b5b557f");
+        std::fs::create_dir_all(&cache).expect("cache dir should be created");
+        let mut lockfile = String::new();
+        for version in ["1.0.0", "1.1.0"] {
+            let archive = crate_archive(
+                &format!("hello-{version}"),
+                &[
+                    ("Cargo.toml", "[package]\n"),
+                    ("Cargo.toml.orig", "[package]\n"),
+                    (".gitignore", "/target\n"),
+                    ("src/lib.rs", ""),
+                    ("src/.gitattributes", ""),
+                ],
+            );
+            std::fs::File::create(cache.join(format!("hello-{version}.crate")))
+                .and_then(|mut f| f.write_all(&archive))
+                .expect("crate should be written");
+            lockfile += &format!(
+                "[[package]]\nname = \"hello\"\nversion = \"{version}\"\nsource = \"{}\"\nchecksum = \"{:x}\"\n\n",
+                "registry+https://github.com/rust-lang/crates.io-index",
+                Sha256::digest(&archive)
+            );
+        }
+        let lockfile = Lockfile::from_str(&lockfile).expect("fixture should parse");
+        let vendor_dir = temp_dir::TempDir::new().expect("temp dir should be created");
+        std::fs::create_dir_all(vendor_dir.path().join("stale"))
+            .and_then(|_| {
+                std::fs::write(vendor_dir.path().join("stale/.cargo-checksum.json"), "{}")
+            })
+            .expect("stale crate should be written");
+
+        let config = vendor(home.path(), &lockfile.packages, vendor_dir.path(), false)
+            .expect("packages should be vendored");
+
+        let dirs = std::fs::read_dir(vendor_dir.path())
+            .expect("vendor dir should exist")
+            .map(|e| e.expect("entry should be listed").file_name())
+            .sorted()
+            .collect_vec();
+        assert_eq!(dirs, ["hello", "hello-1.0.0"]);
+        let checksums =
+            std::fs::read_to_string(vendor_dir.path().join("hello/.cargo-checksum.json"))
+                .expect("checksums should be written");
+        let empty = format!("{:x}", Sha256::digest(""));
+        let package = format!("{:x}", Sha256::digest("[package]\n"));
+        assert_eq!(
+            checksums,
+            format!(
+                r#"{{"files":{{"Cargo.toml":"{package}","Cargo.toml.orig":"{package}","src/lib.rs":"{empty}"}},"package":"{}"}}"#,
+                lockfile.packages[1]
+                    .checksum
+                    .as_ref()
+                    .expect("fixture has checksum")
+            )
+        );
+        assert!(config.starts_with("[source.crates-io]\nreplace-with = \"vendored-sources\"\n"));
+    }
+}
@@ -0,0 +1,135 @@
+//! Verification of crates in cargo's registry cache against lockfile checksums.
+
+use std::path::{Path, PathBuf};
+
+use cargo_lock::{Checksum, Package};
+use sha2::{Digest as _, Sha256};
+
+use crate::cargo_home;
+
+/// A registry cache entry which does not hold the crate the lockfile expects.
+#[derive(Debug, PartialEq, Eq)]
+pub struct BadCrate {
+    pub name: String,
+    pub version: String,
+    pub path: PathBuf,
+    pub problem: Problem,
+}
+
+#[derive(thiserror::Error, Debug, PartialEq, Eq)]
+pub enum Problem {
+    #[error("missing")]
+    Missing,
+    #[error("unreadable: {0}")]
+    Unreadable(String),
+    #[error("checksum mismatch, expected {expected}, found {actual}")]
+    Corrupted {
+        expected: Checksum,
+        actual: Checksum,
+    },
+}
+
+/// Recompute checksums of the `.crate` files of `packages` in the registry cache of `cargo_home`.
+///
+/// Only registry packages with a checksum in the lockfile are verified.
+pub fn verify<'a>(
+    cargo_home: &Path,
+    packages: impl IntoIterator<Item = &'a Package>,
+) -> Vec<BadCrate> {
+    packages
+        .into_iter()
+        .filter_map(|p| {
+            let expected = p.checksum.as_ref()?;
+            let path = cargo_home::cached_crate_path(cargo_home, p)?;
+            let problem = match std::fs::read(&path) {
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => Problem::Missing,
+                Err(e) => Problem::Unreadable(e.to_string()),
+                Ok(contents) => {
+                    let actual = Checksum::from(<[u8; 32]>::from(Sha256::digest(contents)));
+                    if actual == *expected {
+                        return None;
+                    }
+                    Problem::Corrupted {
+                        expected: expected.clone(),
+                        actual,
+                    }
+                }
+            };
+            Some(BadCrate {
+                name: p.name.to_string(),
+                version: p.version.to_string(),
+                path,
+                problem,
+            })
+        })
+        .collect()
+}
+
+/// Remove corrupted and unreadable crates, so that cargo downloads them again.
+pub fn remove(bad: &[BadCrate]) -> Result<(), std::io::Error> {
+    bad.iter()
+        .filter(|b| b.problem != Problem::Missing)
+        .try_for_each(|b| std::fs::remove_file(&b.path))
+}
+
+#[cfg(test)]
+mod test {
+    use std::str::FromStr as _;
+
+    use cargo_lock::Lockfile;
+    use itertools::Itertools as _;
+
+    use super::{Problem, remove, verify};
+
+    const LOCKFILE: &str = r#"
+[[package]]
+name = "good"
+version = "1.0.0"
+source = "registry+https://github.com/rust-lang/crates.io-index"
+checksum = "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824"
+
+[[package]]
+name = "corrupted"
+version = "1.0.0"
+source = "registry+https://github.com/rust-lang/crates.io-index"
+checksum = "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824"
+
+[[package]]
+name = "missing"
+version = "1.0.0"
+source = "registry+https://github.com/rust-lang/crates.io-index"
+checksum = "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824"
+
+[[package]]
+name = "unchecked"
+version = "1.0.0"
+source = "registry+https://github.com/rust-lang/crates.io-index"
+"#;
+
+    #[test]
+    fn finds_missing_and_corrupted_crates_and_removes_corrupted() {
+        let lockfile = Lockfile::from_str(LOCKFILE).expect("fixture should parse");
+        let home = temp_dir::TempDir::new().expect("temp dir should be created");
+        let cache = home
+            .path()
+            .join("registry/cache/index.crates.io-1949cf8c6b5b557f");
+        std::fs::create_dir_all(&cache).expect("cache dir should be created");
+        // The checksum in the fixture is SHA-256 of "hello".
+        for (name, contents) in [("good", "hello"), ("corrupted", "hullo"), ("unchecked", "")] {
+            std::fs::write(cache.join(format!("{name}-1.0.0.crate")), contents)
+                .expect("crate should be written");
+        }
+
+        let bad = verify(home.path(), &lockfile.packages);
+
+        assert_eq!(
+            bad.iter()
+                .map(|b| (b.name.as_str(), matches!(b.problem, Problem::Missing)))
+                .collect_vec(),
+            vec![("corrupted", false), ("missing", true)]
+        );
+        remove(&bad).expect("corrupted crate should be removed");
+        assert!(!cache.join("corrupted-1.0.0.crate").exists());
+        assert!(cache.join("good-1.0.0.crate").exists());
+    }
+}
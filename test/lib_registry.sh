@@ -0,0 +1,39 @@
+# Helpers for tests with crates packaged locally and served by a registry over
+# HTTP, a static directory served by python, so that none of the tests using
+# them needs network access. Meant to be sourced, with D set to the test's
+# scratch directory.
+
+# Package crate $1, version 0.1.0, in $D/$1, with $2 as its lib.rs if given,
+# setting CRATE to its .crate file and SUM to its SHA-256 checksum.
+package_crate() {
+	mkdir "$D/$1"
+	(
+		cd "$D/$1"
+		cargo -q init . --lib --vcs none --name "$1"
+		[ -z "$2" ] || echo "$2" >src/lib.rs
+		cargo -q package --offline --no-verify
+	)
+	CRATE="$D/$1/target/package/$1-0.1.0.crate"
+	SUM=$(sha256sum "$CRATE" | cut -d ' ' -f 1)
+}
+
+# Serve directory $1 over HTTP in the background, setting SERVER to the
+# server's pid and URL to its address.
+serve() {
+	# HTTP/1.1, like real registries, as clients may reuse connections.
+	python3 -u - "$1" >"$D/server.log" 2>&1 <<'PYTHON' &
+import functools, http.server, sys
+class Handler(http.server.SimpleHTTPRequestHandler):
+    protocol_version = "HTTP/1.1"
+handler = functools.partial(Handler, directory=sys.argv[1])
+server = http.server.ThreadingHTTPServer(("127.0.0.1", 0), handler)
+print(f"serving on port {server.server_port} ")
+server.serve_forever()
+PYTHON
+	SERVER=$!
+	while ! PORT=$(sed -n 's/.* port \([0-9]*\) .*/\1/p' "$D/server.log" 2>/dev/null) ||
+		[ -z "$PORT" ]; do
+		sleep 0.1
+	done
+	URL="http://127.0.0.1:$PORT"
+}
@@ -19,6 +19,30 @@ echo "testing fixture 1 (fetch+vendor, synthetic crates)" >&2
 echo "testing yanked crate versions" >&2
 "$DIR"/test_yanked.sh
 
+# lockfiles with [replace] entries pointing to a git repository
+echo "testing replaced packages" >&2
+"$DIR"/test_replace.sh
+
+# workspace members of the same name in several lockfiles
+echo "testing package selection" >&2
+"$DIR"/test_package.sh
+
+# lockfiles with packages from local registries and directories, offline
+echo "testing local registry and directory sources" >&2
+"$DIR"/test_local_sources.sh
+
+# crates downloaded from a sparse registry without cargo, offline
+echo "testing native downloads" >&2
+"$DIR"/test_native.sh
+
+# cargo run with a timeout, terminated with cargo-lock-fetch
+echo "testing interrupts" >&2
+"$DIR"/test_interrupt.sh
+
+# a crate deleted from its registry, offline
+echo "testing keep-going" >&2
+"$DIR"/test_keep_going.sh
+
 # less popular crates, with more dependencies, large test
 echo "testing fixture 2 (fetch+vendor, synthetic crates)" >&2
 "$DIR"/test_sets.sh fetch vendor <"$DIR"/sets_fixture_3_150_10_20250727
@@ -0,0 +1,65 @@
+#!/bin/sh
+
+# Verify that terminating cargo-lock-fetch while it runs cargo with --timeout,
+# in a process group of its own, terminates cargo and its children too.
+#
+# Cargo is replaced by a script which starts a child and waits for it.
+
+set -e
+
+ROOT=$(CDPATH='' cd -- "$(dirname -- "$0")" && pwd)/..
+
+cd "$ROOT"
+
+cargo -q build
+TARGET=$(realpath target/debug/cargo-lock-fetch)
+
+D=$(mktemp -d)
+PARENT=
+cleanup() {
+	[ -z "$PARENT" ] || kill "$PARENT" 2>/dev/null || true
+	[ ! -s "$D/pids" ] || kill $(cat "$D/pids") 2>/dev/null || true
+	rm -rf "$D"
+}
+trap cleanup EXIT
+
+cat >"$D/cargo" <<EOF
+#!/bin/sh
+sleep 300 >/dev/null 2>&1 &
+echo "\$\$ \$!" >"$D/pids"
+wait
+EOF
+chmod +x "$D/cargo"
+
+mkdir "$D/app"
+cat >"$D/app/Cargo.lock" <<EOF
+version = 4
+
+[[package]]
+name = "itoa"
+version = "1.0.18"
+source = "registry+https://github.com/rust-lang/crates.io-index"
+checksum = "8f42a60cbdf9a97f5d2305f08a87dc4e09308d1276d28c869c684d7777685682"
+EOF
+
+CARGO_HOME="$D/home" CARGO="$D/cargo" "$TARGET" lock-fetch -q --timeout 600 \
+	--lockfile-path "$D/app/Cargo.lock" &
+PARENT=$!
+while ! [ -s "$D/pids" ]; do
+	sleep 0.1
+done
+kill -TERM "$PARENT"
+wait "$PARENT" || true
+PARENT=
+sleep 0.5
+# Terminated processes no one waits for remain as zombies, which are fine.
+for pid in $(cat "$D/pids"); do
+	case $(ps -o stat= -p "$pid" || true) in
+	'' | Z*) ;;
+	*)
+		echo "fail: cargo process $pid survived"
+		exit 1
+		;;
+	esac
+done
+echo "pass: cargo terminated with cargo-lock-fetch"
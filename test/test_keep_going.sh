@@ -0,0 +1,145 @@
+#!/bin/sh
+
+# Verify that a crate deleted from its registry is diagnosed, failing with
+# exit code 7, and that --keep-going then fetches all crates that are still
+# available, leaving out the crates depending on it, and exits with code 3,
+# also when downloading natively.
+
+set -e
+
+ROOT=$(CDPATH='' cd -- "$(dirname -- "$0")" && pwd)/..
+
+cd "$ROOT"
+
+cargo -q build
+TARGET=$(realpath target/debug/cargo-lock-fetch)
+. test/lib_registry.sh
+
+D=$(mktemp -d)
+SERVER=
+cleanup() {
+	[ -z "$SERVER" ] || kill "$SERVER"
+	rm -rf "$D"
+}
+trap cleanup EXIT
+
+# "gone" is in the index, but its download was deleted, and "user" depends on it.
+for name in good gone user; do
+	package_crate "$name"
+	eval "SUM_$name=$SUM"
+	[ "$name" = gone ] || {
+		mkdir -p "$D/www/dl/$name/0.1.0"
+		cp "$CRATE" "$D/www/dl/$name/0.1.0/download"
+	}
+done
+mkdir -p "$D/www/index/go/od" "$D/www/index/go/ne" "$D/www/index/us/er"
+index_entry() {
+	echo "{\"name\":\"$1\",\"vers\":\"0.1.0\",\"deps\":[$3],\"cksum\":\"$2\",\"features\":{},\"yanked\":false}"
+}
+index_entry good "$SUM_good" >"$D/www/index/go/od/good"
+index_entry gone "$SUM_gone" >"$D/www/index/go/ne/gone"
+index_entry user "$SUM_user" '{"name":"gone","req":"^0.1.0","features":[],"optional":false,"default_features":true,"target":null,"kind":"normal"}' \
+	>"$D/www/index/us/er/user"
+serve "$D/www"
+echo "{\"dl\":\"$URL/dl\"}" >"$D/www/index/config.json"
+
+mkdir "$D/app"
+cat >"$D/app/Cargo.lock" <<EOF
+version = 4
+
+[[package]]
+name = "app"
+version = "0.1.0"
+dependencies = [
+ "good",
+ "user",
+]
+
+[[package]]
+name = "good"
+version = "0.1.0"
+source = "sparse+$URL/index/"
+checksum = "$SUM_good"
+
+[[package]]
+name = "gone"
+version = "0.1.0"
+source = "sparse+$URL/index/"
+checksum = "$SUM_gone"
+
+[[package]]
+name = "user"
+version = "0.1.0"
+source = "sparse+$URL/index/"
+checksum = "$SUM_user"
+dependencies = [
+ "gone",
+]
+EOF
+
+export CARGO_HOME="$D/home"
+STATUS=0
+"$TARGET" lock-fetch --lockfile-path "$D/app/Cargo.lock" 2>"$D/stderr" || STATUS=$?
+[ "$STATUS" = 7 ] || {
+	echo "fail: expected exit code 7 for a cargo failure, got $STATUS"
+	exit 1
+}
+grep -qF "\`gone 0.1.0\` from \`reg1\` ($URL/index/): HTTP status 404" "$D/stderr" || {
+	cat "$D/stderr"
+	echo "fail: deleted crate not diagnosed"
+	exit 1
+}
+echo "pass: diagnosed deleted crate"
+
+STATUS=0
+"$TARGET" lock-fetch --keep-going --lockfile-path "$D/app/Cargo.lock" \
+	--report "$D/report.json" 2>"$D/stderr" || STATUS=$?
+[ "$STATUS" = 3 ] || {
+	cat "$D/stderr"
+	echo "fail: expected exit code 3, got $STATUS"
+	exit 1
+}
+find "$CARGO_HOME/registry/cache" -name good-0.1.0.crate | grep -q . || {
+	echo "fail: available crate not fetched"
+	exit 1
+}
+sed -n '/could not fetch crates:/,$p' "$D/stderr" >"$D/failed"
+grep -q "^  gone 0.1.0 (" "$D/failed" &&
+	grep -q "^  user 0.1.0 (" "$D/failed" &&
+	! grep -q "good" "$D/failed" || {
+	cat "$D/stderr"
+	echo "fail: wrong crates reported"
+	exit 1
+}
+sed -n '/"failed_packages"/,/"local_packages"/p' "$D/report.json" >"$D/reported"
+grep -q '"name": "gone"' "$D/reported" &&
+	grep -q '"name": "user"' "$D/reported" &&
+	! grep -q '"name": "good"' "$D/reported" || {
+	cat "$D/report.json"
+	echo "fail: wrong crates in report"
+	exit 1
+}
+echo "pass: fetched available crates"
+
+export CARGO_HOME="$D/home-native"
+STATUS=0
+"$TARGET" lock-fetch --keep-going --native --lockfile-path "$D/app/Cargo.lock" \
+	2>"$D/stderr" || STATUS=$?
+[ "$STATUS" = 3 ] || {
+	cat "$D/stderr"
+	echo "fail: expected exit code 3 with --native, got $STATUS"
+	exit 1
+}
+find "$CARGO_HOME/registry/cache" -name good-0.1.0.crate | grep -q . || {
+	echo "fail: available crate not downloaded"
+	exit 1
+}
+sed -n '/could not fetch crates:/,$p' "$D/stderr" >"$D/failed"
+grep -q "^  gone 0.1.0 (.*404" "$D/failed" &&
+	grep -q "^  user 0.1.0 (.*depends on" "$D/failed" &&
+	! grep -q "good" "$D/failed" || {
+	cat "$D/stderr"
+	echo "fail: wrong crates reported with --native"
+	exit 1
+}
+echo "pass: downloaded available crates"
@@ -0,0 +1,73 @@
+#!/bin/sh
+
+# Verify that packages from local registries and directories are fetched
+# and vendored.
+#
+# Both sources are built on disk, without a registry served over HTTP.
+
+set -e
+
+ROOT=$(CDPATH='' cd -- "$(dirname -- "$0")" && pwd)/..
+
+cd "$ROOT"
+
+cargo -q build
+TARGET=$(realpath target/debug/cargo-lock-fetch)
+. test/lib_registry.sh
+
+D=$(mktemp -d)
+cleanup() {
+	rm -rf "$D"
+}
+trap cleanup EXIT
+
+package_crate localdep
+
+mkdir -p "$D/sources/registry/index/lo/ca"
+cp "$CRATE" "$D/sources/registry/"
+cat >"$D/sources/registry/index/lo/ca/localdep" <<EOF
+{"name":"localdep","vers":"0.1.0","deps":[],"cksum":"$SUM","features":{},"yanked":false}
+EOF
+
+mkdir -p "$D/sources/directory"
+tar -xzf "$CRATE" -C "$D/sources/directory"
+echo "{\"files\":{},\"package\":\"$SUM\"}" >"$D/sources/directory/localdep-0.1.0/.cargo-checksum.json"
+
+for kind in local-registry directory; do
+	case $kind in
+	local-registry) source="$D/sources/registry" ;;
+	directory) source="$D/sources/directory" ;;
+	esac
+	mkdir "$D/$kind"
+	cat >"$D/$kind/Cargo.lock" <<EOF
+version = 4
+
+[[package]]
+name = "localdep"
+version = "0.1.0"
+source = "$kind+file://$source"
+checksum = "$SUM"
+
+[[package]]
+name = "package"
+version = "0.1.0"
+dependencies = [
+ "localdep",
+]
+EOF
+	"$TARGET" lock-fetch -q --lockfile-path "$D/$kind/Cargo.lock" -- --offline || {
+		echo "fail: $kind: fetch failed"
+		exit 1
+	}
+	echo "pass: $kind: fetched"
+	"$TARGET" lock-fetch -q --lockfile-path "$D/$kind/Cargo.lock" \
+		--vendor "$D/$kind/vendor" -- --offline || {
+		echo "fail: $kind: vendor failed"
+		exit 1
+	}
+	[ -f "$D/$kind/vendor/localdep/src/lib.rs" ] || {
+		echo "fail: $kind: localdep not vendored"
+		exit 1
+	}
+	echo "pass: $kind: vendored"
+done
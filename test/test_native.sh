@@ -0,0 +1,99 @@
+#!/bin/sh
+
+# Verify that --native downloads crates from a sparse registry without cargo,
+# so that cargo can build with them offline afterwards, and that nothing is
+# fetched again.
+
+set -e
+
+ROOT=$(CDPATH='' cd -- "$(dirname -- "$0")" && pwd)/..
+
+cd "$ROOT"
+
+cargo -q build
+TARGET=$(realpath target/debug/cargo-lock-fetch)
+. test/lib_registry.sh
+
+D=$(mktemp -d)
+SERVER=
+cleanup() {
+	[ -z "$SERVER" ] || kill "$SERVER"
+	rm -rf "$D"
+}
+trap cleanup EXIT
+
+package_crate localdep 'pub const ANSWER: u32 = 42;'
+
+mkdir -p "$D/www/index/lo/ca" "$D/www/dl/localdep/0.1.0"
+cp "$CRATE" "$D/www/dl/localdep/0.1.0/download"
+cat >"$D/www/index/lo/ca/localdep" <<EOF
+{"name":"localdep","vers":"0.1.0","deps":[],"cksum":"$SUM","features":{},"yanked":false}
+EOF
+serve "$D/www"
+echo "{\"dl\":\"$URL/dl\"}" >"$D/www/index/config.json"
+
+mkdir "$D/app"
+cat >"$D/app/Cargo.lock" <<EOF
+version = 4
+
+[[package]]
+name = "app"
+version = "0.1.0"
+dependencies = [
+ "localdep",
+]
+
+[[package]]
+name = "localdep"
+version = "0.1.0"
+source = "sparse+$URL/index/"
+checksum = "$SUM"
+EOF
+
+export CARGO_HOME="$D/home"
+"$TARGET" lock-fetch -q --native --lockfile-path "$D/app/Cargo.lock" \
+	--report "$D/report.json" || {
+	echo "fail: native download failed"
+	exit 1
+}
+# Cargo had nothing left to fetch, so it did not run.
+grep -q '"cargo_status": *null' "$D/report.json" || {
+	cat "$D/report.json"
+	echo "fail: cargo reported as run"
+	exit 1
+}
+echo "pass: downloaded"
+
+cd "$D/app"
+mkdir src .cargo
+echo 'fn main() { assert_eq!(localdep::ANSWER, 42); }' >src/main.rs
+cat >Cargo.toml <<EOF
+[package]
+name = "app"
+version = "0.1.0"
+edition = "2021"
+
+[dependencies]
+localdep = { version = "0.1.0", registry = "local" }
+EOF
+cat >.cargo/config.toml <<EOF
+[registries.local]
+index = "sparse+$URL/index/"
+EOF
+kill "$SERVER"
+SERVER=
+cargo -q build --offline || {
+	echo "fail: offline build with downloaded crates failed"
+	exit 1
+}
+echo "pass: built offline"
+
+"$TARGET" lock-fetch -q --lockfile-path "$D/app/Cargo.lock" --stamp "$D/stamp" || {
+	echo "fail: fetching cached crates without a registry failed"
+	exit 1
+}
+[ -s "$D/stamp" ] || {
+	echo "fail: stamp not written"
+	exit 1
+}
+echo "pass: nothing to do"
@@ -0,0 +1,65 @@
+#!/bin/sh
+
+# Verify that --package selects the dependencies of a workspace member in
+# every lockfile, also when members of different workspaces share a name.
+#
+# Only the generated files are inspected, with --dry-run.
+
+set -e
+
+ROOT=$(CDPATH='' cd -- "$(dirname -- "$0")" && pwd)/..
+
+cd "$ROOT"
+
+cargo -q build
+TARGET=$(realpath target/debug/cargo-lock-fetch)
+
+D=$(mktemp -d)
+cleanup() {
+	rm -rf "$D"
+}
+trap cleanup EXIT
+
+# Both workspaces have an "xtask" member, with different dependencies.
+lockfile() {
+	cat <<LOCK
+version = 4
+
+[[package]]
+name = "app"
+version = "0.1.0"
+dependencies = [
+ "serde",
+]
+
+[[package]]
+name = "serde"
+version = "1.0.228"
+source = "registry+https://github.com/rust-lang/crates.io-index"
+
+[[package]]
+name = "xtask"
+version = "0.1.0"
+dependencies = [
+ "$1",
+]
+
+[[package]]
+name = "$1"
+version = "$2"
+source = "registry+https://github.com/rust-lang/crates.io-index"
+LOCK
+}
+lockfile itoa 1.0.18 >"$D/a.lock"
+lockfile memchr 2.8.0 >"$D/b.lock"
+
+"$TARGET" lock-fetch --dry-run -p xtask --lockfile-path "$D/a.lock" \
+	--lockfile-path "$D/b.lock" >"$D/plan"
+grep -q "^\[dependencies.itoa\]" "$D/plan" &&
+	grep -q "^\[dependencies.memchr\]" "$D/plan" &&
+	! grep -q "^\[dependencies.serde\]" "$D/plan" || {
+	cat "$D/plan"
+	echo "fail: wrong crates selected"
+	exit 1
+}
+echo "pass: selected dependencies of xtask in both workspaces"
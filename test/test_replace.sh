@@ -0,0 +1,70 @@
+#!/bin/sh
+
+# Verify that packages replaced with `[replace]` are fetched and vendored
+# from the replacement, not from their original source.
+#
+# The replacement is a local git repository with a copy of itoa carrying
+# an extra marker file, which the original crate from crates.io lacks.
+
+set -e
+
+ROOT=$(CDPATH='' cd -- "$(dirname -- "$0")" && pwd)/..
+
+cd "$ROOT"
+
+cargo -q build
+TARGET=$(realpath target/debug/cargo-lock-fetch)
+
+D=$(mktemp -d)
+cleanup() {
+	rm -rf "$D"
+}
+trap cleanup EXIT
+
+mkdir "$D/itoa"
+cd "$D/itoa"
+cargo -q init . --lib --vcs none --name itoa
+sed -i 's/^version = .*/version = "1.0.11"/' Cargo.toml
+echo "replacement" >MARKER
+git init -q
+git add .
+git -c user.name=test -c user.email=test@example.com commit -q -m replacement
+cd - >/dev/null
+
+mkdir "$D/package"
+cd "$D/package"
+cargo -q init . --vcs none --name package
+cargo -q add itoa@=1.0.11
+cat >>Cargo.toml <<EOF
+
+[replace]
+"itoa:1.0.11" = { git = "file://$D/itoa" }
+EOF
+cargo -q generate-lockfile
+cd - >/dev/null
+
+CARGO_HOME="$D/cargo_home"
+export CARGO_HOME
+"$TARGET" lock-fetch -q --lockfile-path "$D/package/Cargo.lock"
+find "$CARGO_HOME/git/checkouts" -name MARKER | grep -q . || {
+	echo "fail: replacement not fetched"
+	exit 1
+}
+(cd "$D/package" && cargo -q fetch --frozen) || {
+	echo "fail: cargo fetch --frozen failed"
+	exit 1
+}
+echo "pass: fetched replacement"
+
+(cd "$D/package" && cargo -q vendor "$D/vendor_expected" --versioned-dirs >/dev/null)
+"$TARGET" lock-fetch -q --lockfile-path "$D/package/Cargo.lock" \
+	--vendor "$D/vendor_actual" -- --versioned-dirs
+if ! diff -Naur "$D/vendor_expected" "$D/vendor_actual" >/dev/null; then
+	echo "fail: vendor mismatch"
+	exit 1
+fi
+find "$D/vendor_actual" -name MARKER | grep -q . || {
+	echo "fail: replacement not vendored"
+	exit 1
+}
+echo "pass: vendored replacement"
@@ -27,6 +27,7 @@ cd - >/dev/null
 rm -fr "$D/fake"
 
 cargo -q run -- lock-fetch -q --lockfile-path "$D/Cargo.lock" --vendor "$D/vendor.actual/" -- --versioned-dirs
+cargo -q run -- lock-fetch -q --native --lockfile-path "$D/Cargo.lock" --vendor "$D/vendor.native/" --versioned-dirs
 
 if diff -Naur "$D/vendor.expected/" "$D/vendor.actual/" >/dev/null; then
 	echo "pass: vendored $(find "$D/vendor.actual" -maxdepth 1 -mindepth 1 -type d | wc -l) crates"
@@ -34,3 +35,9 @@ else
 	echo fail
 	exit 3
 fi
+if diff -Naur "$D/vendor.expected/" "$D/vendor.native/" >/dev/null; then
+	echo "pass: vendored natively too"
+else
+	echo "fail: native"
+	exit 3
+fi